@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use csv_async::{AsyncReaderBuilder, AsyncWriterBuilder};
+use krwallet::{
+    CsvStreamReader, CsvStreamWriter,
+    wallet::{config::WalletConfig, processor::TransactionProcessor},
+};
+
+const TRANSACTIONS: usize = 100_000;
+
+const CLIENTS: usize = 1_000;
+
+fn transactions() -> String {
+    let mut csv = String::from("type,client,tx,amount\n");
+    for tx in 0..TRANSACTIONS {
+        csv.push_str(&format!("deposit,{},{},1.5\n", tx % CLIENTS, tx));
+    }
+    csv
+}
+
+async fn run(csv: &str, batch_size: usize) {
+    let config = WalletConfig::default().with_batching(batch_size, Duration::from_millis(10));
+    let mut processor = TransactionProcessor::with_config(4, 20, config).await;
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv.as_bytes()),
+    };
+    processor.process(reader).await.unwrap();
+
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(tokio::io::sink()),
+    };
+    processor.output(writer).await.unwrap();
+    processor.shutdown().await.unwrap();
+}
+
+fn batching(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build()
+        .unwrap();
+    let csv = transactions();
+
+    let mut group = c.benchmark_group("delivery");
+    group.throughput(Throughput::Elements(TRANSACTIONS as u64));
+    group.sample_size(10);
+    for batch_size in [1, 16, 128] {
+        group.bench_with_input(
+            BenchmarkId::new("batch_size", batch_size),
+            &batch_size,
+            |b, &batch_size| b.iter(|| runtime.block_on(run(&csv, batch_size))),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, batching);
+criterion_main!(benches);
@@ -1,16 +1,115 @@
-use std::{env, error::Error};
+use std::{
+    env,
+    error::Error,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use krwallet::{CsvStreamReader, CsvStreamWriter, wallet::processor::TransactionProcessor};
+use rust_decimal::Decimal;
+
+use krwallet::{
+    CsvStreamReader, CsvStreamWriter, ProcessorError, ProcessorResult,
+    channel_actor::Supervision,
+    input::{self, CsvDialect, InputStream},
+    wallet::{config::WalletConfig, fraud::FraudConfig, processor::TransactionProcessor},
+};
 
 // Someday we will read these const variables from config
 const ACTOR_COUNT: usize = 4;
 
 const BUFFER_SIZE: usize = 20;
 
+const BATCH_FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(60);
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <input_file.csv>", args[0]);
+    let mut args = env::args();
+    let program = args.next().unwrap_or_default();
+
+    let mut inputs = Vec::new();
+    // Cleared when an input argument cannot be expanded, the run then exits with an error
+    let mut inputs_complete = true;
+    let mut merge_by_timestamp = false;
+    let mut monotonic_timestamps = false;
+    let mut limits_path = None;
+    let mut admin_overdraft_max = None;
+    let mut fraud_report_path = None;
+    let mut expired_report_path = None;
+    let mut error_report_path = None;
+    let mut dead_letter_report_path = None;
+    let mut reorder_buffer_size = 0;
+    let mut batch_size = 0;
+    let mut reply_timeout = REPLY_TIMEOUT;
+    let mut auto_freeze = false;
+    let mut supervise = false;
+    let mut dialect = CsvDialect::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--limits" => limits_path = args.next(),
+            "--admin-overdrafts" => {
+                admin_overdraft_max = Some(
+                    args.next()
+                        .and_then(|max| max.parse::<Decimal>().ok())
+                        .expect("--admin-overdrafts expects the highest limit"),
+                )
+            }
+            "--fraud-report" => fraud_report_path = args.next(),
+            "--auto-freeze" => auto_freeze = true,
+            "--supervise" => supervise = true,
+            "--reorder-buffer" => {
+                reorder_buffer_size = args
+                    .next()
+                    .and_then(|size| size.parse().ok())
+                    .expect("--reorder-buffer expects a size")
+            }
+            "--batch-size" => {
+                batch_size = args
+                    .next()
+                    .and_then(|size| size.parse().ok())
+                    .expect("--batch-size expects a size")
+            }
+            "--reply-timeout" => {
+                reply_timeout = args
+                    .next()
+                    .and_then(|seconds| seconds.parse().ok())
+                    .map(Duration::from_secs)
+                    .expect("--reply-timeout expects a number of seconds")
+            }
+            "--expired-report" => expired_report_path = args.next(),
+            "--error-report" => error_report_path = args.next(),
+            "--dead-letter-report" => dead_letter_report_path = args.next(),
+            "--merge-by-timestamp" => merge_by_timestamp = true,
+            "--monotonic-timestamps" => monotonic_timestamps = true,
+            "--delimiter" => dialect = dialect.with_delimiter(single_byte(args.next(), "--delimiter")),
+            "--quote" => dialect = dialect.with_quote(single_byte(args.next(), "--quote")),
+            "--no-quoting" => dialect = dialect.without_quoting(),
+            "--no-headers" => {
+                let columns = args.next().expect("--no-headers expects the column names");
+                dialect = dialect.without_headers(columns.split(',').map(str::to_string).collect());
+            }
+            "--column" => {
+                let mapping = args.next().unwrap_or_default();
+                let (from, to) = mapping.split_once('=').expect("--column expects <from>=<to>");
+                dialect = dialect.with_column_name(from, to);
+            }
+            _ => match expand_input(&arg) {
+                Ok(paths) => inputs.extend(paths),
+                Err(e) => inputs_complete = skip_input(Path::new(&arg), e),
+            },
+        }
+    }
+
+    if inputs.is_empty() && inputs_complete {
+        eprintln!(
+            "Usage: {} [--limits <limits.csv>] [--admin-overdrafts <max>] [--fraud-report <report.csv>] \
+             [--auto-freeze] [--supervise] [--reorder-buffer <size>] [--batch-size <size>] \
+             [--reply-timeout <seconds>] [--expired-report <expired.csv>] [--error-report <errors.csv>] \
+             [--dead-letter-report <dead.csv>] [--merge-by-timestamp] [--monotonic-timestamps] \
+             [--delimiter <char>] [--quote <char>] [--no-quoting] [--no-headers <columns>] \
+             [--column <from>=<to>]... <input.csv | directory | glob>...",
+            program
+        );
         std::process::exit(1);
     }
 
@@ -25,23 +124,191 @@ fn main() -> Result<(), Box<dyn Error>> {
     // The code should fail if the runtime could not be build
     let runtime = builder.enable_all().build()?;
 
-    runtime.block_on(async move {
-        let mut input_file = tokio::fs::File::open(&args[1])
-            .await
-            .expect("Input file does not exist");
+    let complete = runtime.block_on(async move {
+        let mut config = WalletConfig::default()
+            .with_reorder_buffer(reorder_buffer_size)
+            .with_monotonic_timestamps(monotonic_timestamps)
+            .with_batching(batch_size, BATCH_FLUSH_INTERVAL)
+            .with_reply_timeout(reply_timeout)
+            .with_error_report(error_report_path.is_some());
+        if supervise {
+            config = config.with_supervision(Supervision::default());
+        }
+        if let Some(max) = admin_overdraft_max {
+            config = config.with_admin_overdrafts(max);
+        }
+
+        if let Some(limits_path) = limits_path {
+            let mut limits_file = tokio::fs::File::open(&limits_path)
+                .await
+                .expect("Limits file does not exist");
+
+            let reader = csv_async::AsyncReaderBuilder::new()
+                .trim(csv_async::Trim::All)
+                .create_deserializer(&mut limits_file);
+
+            config = config
+                .load_overdraft_limits(CsvStreamReader { reader })
+                .await
+                .expect("Limits file is invalid");
+        }
 
-        let reader = csv_async::AsyncReaderBuilder::new()
-            .trim(csv_async::Trim::All)
-            .create_deserializer(&mut input_file);
+        if fraud_report_path.is_some() || auto_freeze {
+            config = config.with_fraud(FraudConfig {
+                auto_freeze,
+                ..FraudConfig::recommended()
+            });
+        }
 
-        let mut transaction_processor = TransactionProcessor::new(ACTOR_COUNT, BUFFER_SIZE).await;
+        let mut transaction_processor = TransactionProcessor::with_config(ACTOR_COUNT, BUFFER_SIZE, config).await;
+        // Inputs that cannot be read are skipped and failed reports reported, the run
+        // then exits with an error
+        let mut complete = inputs_complete;
 
         // Ignoring the errors from TransactionProcessor for now
-        let _ = transaction_processor.process(CsvStreamReader { reader }).await;
+        if merge_by_timestamp {
+            // Merging needs every input open at once
+            let mut readers = Vec::with_capacity(inputs.len());
+            for input in inputs.iter() {
+                match open_input(input, &dialect).await {
+                    Ok(reader) => readers.push(reader),
+                    Err(e) => complete = skip_input(input, e),
+                }
+            }
+            let _ = transaction_processor.process_merged(readers).await;
+        } else {
+            // Each input is only opened once the previous one is done
+            for input in inputs.iter() {
+                match open_input(input, &dialect).await {
+                    Ok(reader) => {
+                        let _ = transaction_processor.process(reader).await;
+                    }
+                    Err(e) => complete = skip_input(input, e),
+                }
+            }
+        }
+
+        for failure in transaction_processor.failures() {
+            eprintln!(
+                "{} failed ({}): {}",
+                failure.actor,
+                if failure.restarted { "restarted" } else { "stopped" },
+                failure.reason
+            );
+        }
+
+        // The fraud report has to be written before the wallets are consumed by the output
+        if let Some(fraud_report_path) = fraud_report_path {
+            let report_file = tokio::fs::File::create(&fraud_report_path)
+                .await
+                .expect("Fraud report file could not be created");
+            let writer = csv_async::AsyncWriterBuilder::new().create_serializer(report_file);
+            let result = transaction_processor.fraud_report(CsvStreamWriter { writer }).await;
+            complete &= succeeded("Fraud report", result);
+        }
 
         let writer = csv_async::AsyncWriterBuilder::new().create_serializer(tokio::io::stdout());
-        let _ = transaction_processor.output(CsvStreamWriter { writer }).await;
+        let result = transaction_processor.output(CsvStreamWriter { writer }).await;
+        complete &= succeeded("Output", result);
+
+        // Parked transactions are only all expired once the output is done
+        if let Some(expired_report_path) = expired_report_path {
+            let report_file = tokio::fs::File::create(&expired_report_path)
+                .await
+                .expect("Expired report file could not be created");
+            let writer = csv_async::AsyncWriterBuilder::new().create_serializer(report_file);
+            let result = transaction_processor.expired_report(CsvStreamWriter { writer }).await;
+            complete &= succeeded("Expired report", result);
+        }
+
+        // Errors are reported as the actors get to the transactions, which is done
+        // once the output is
+        if let Some(error_report_path) = error_report_path {
+            let report_file = tokio::fs::File::create(&error_report_path)
+                .await
+                .expect("Error report file could not be created");
+            let writer = csv_async::AsyncWriterBuilder::new().create_serializer(report_file);
+            let result = transaction_processor.error_report(CsvStreamWriter { writer }).await;
+            complete &= succeeded("Error report", result);
+        }
+
+        if let Some(dead_letter_report_path) = dead_letter_report_path {
+            let report_file = tokio::fs::File::create(&dead_letter_report_path)
+                .await
+                .expect("Dead letter report file could not be created");
+            let writer = csv_async::AsyncWriterBuilder::new().create_serializer(report_file);
+            let result = transaction_processor
+                .dead_letter_report(CsvStreamWriter { writer })
+                .await;
+            complete &= succeeded("Dead letter report", result);
+        }
+
+        // Let every actor finish what it was sent before the runtime goes away
+        let _ = transaction_processor.shutdown().await;
+        complete
     });
 
+    if !complete {
+        std::process::exit(1);
+    }
+
     Ok(())
 }
+
+/// Opens an input, decompressing it while it is read, and reads its header
+async fn open_input(path: &Path, dialect: &CsvDialect) -> ProcessorResult<CsvStreamReader<InputStream>> {
+    let input_stream = input::open(path).await?;
+    dialect.reader(input_stream).await
+}
+
+fn skip_input(path: &Path, e: ProcessorError) -> bool {
+    eprintln!("Skipping input {}: {}", path.display(), e);
+    false
+}
+
+fn succeeded(what: &str, result: ProcessorResult<()>) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{} failed: {}", what, e);
+            false
+        }
+    }
+}
+
+fn single_byte(arg: Option<String>, option: &str) -> u8 {
+    match arg.as_deref().map(str::as_bytes) {
+        Some([byte]) => *byte,
+        _ => panic!("{} expects a single character", option),
+    }
+}
+
+/// Turns an input argument into the files to process: a directory stands for the
+/// CSV files in it (plain, .gz or .zst), a pattern for the files it matches, both in
+/// name order. Fails on a directory that cannot be read or an invalid pattern.
+fn expand_input(arg: &str) -> ProcessorResult<Vec<PathBuf>> {
+    let path = Path::new(arg);
+    let mut paths: Vec<PathBuf> = if path.is_dir() {
+        std::fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_csv(path))
+            .collect()
+    } else if arg.contains(['*', '?', '[']) {
+        glob::glob(arg)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?
+            .filter_map(Result::ok)
+            .collect()
+    } else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+
+    paths.sort();
+    Ok(paths)
+}
+
+fn is_csv(path: &Path) -> bool {
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    [".csv", ".csv.gz", ".csv.zst"]
+        .iter()
+        .any(|suffix| name.ends_with(suffix))
+}
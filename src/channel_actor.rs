@@ -1,43 +1,370 @@
-use std::time::Duration;
+use std::{
+    any::Any,
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use futures::{FutureExt, future};
+use serde::Serialize;
+use tokio::{
+    sync::{
+        mpsc::{
+            self,
+            error::{SendTimeoutError, TrySendError},
+        },
+        oneshot,
+    },
+    task::JoinHandle,
+};
 
-use tokio::sync::{
-    mpsc::{self, error::TrySendError},
-    oneshot,
+use crate::{
+    ProcessorError, ProcessorResult, map_channel_recv_err, map_channel_send_err, map_channel_send_timeout_err,
 };
 
-use crate::{ProcessorError, ProcessorResult, map_channel_recv_err, map_channel_send_err};
+/// Capacity of the control lane of every actor. Control messages are few and handled
+/// first, so they never need much room.
+const CONTROL_LANE_SIZE: usize = 16;
+
+/// What travels through an actor's mailbox: its own messages, or the request to stop
+#[derive(Debug)]
+enum Envelope<M> {
+    Msg(M),
+    // Messages sent in one channel operation, handled one by one in order
+    Batch(Vec<M>),
+    // Stop once the messages already queued are handled
+    PoisonPill,
+    // Stop right away, the queued messages become dead letters
+    Kill,
+}
+
+impl<M> Envelope<M> {
+    /// The actor messages carried, none for the stop requests
+    fn into_messages(self) -> Vec<M> {
+        match self {
+            Envelope::Msg(msg) => vec![msg],
+            Envelope::Batch(msgs) => msgs,
+            Envelope::PoisonPill | Envelope::Kill => Vec::new(),
+        }
+    }
+}
+
+/// The receiving end of an actor's two lanes. Control messages (queries, admin
+/// commands, kill) are always handled before the bulk messages queued next to them.
+struct Mailbox<M> {
+    control: mpsc::Receiver<Envelope<M>>,
+    messages: mpsc::Receiver<Envelope<M>>,
+    // What is left of the batch being handled
+    batch: VecDeque<M>,
+}
+
+impl<M: Send> Mailbox<M> {
+    fn channel(buf_size: usize, options: &ActorOptions<M>) -> (ActorRef<M>, Self) {
+        let (control_tx, control) = mpsc::channel(CONTROL_LANE_SIZE);
+        let (sender, messages) = mpsc::channel(buf_size);
+        let actor_ref = ActorRef {
+            sender,
+            control: control_tx,
+            name: options.name.as_str().into(),
+            dead_letters: options.dead_letters.clone(),
+        };
+
+        let mailbox = Self {
+            control,
+            messages,
+            batch: VecDeque::new(),
+        };
+        (actor_ref, mailbox)
+    }
+
+    /// The next message to handle, or None once the actor has to stop
+    async fn next(&mut self) -> Option<M> {
+        loop {
+            let envelope = if self.batch.is_empty() {
+                tokio::select! {
+                    biased;
+                    Some(envelope) = self.control.recv() => envelope,
+                    envelope = self.messages.recv() => envelope?,
+                }
+            } else {
+                // Control messages still go first in the middle of a batch
+                match self.control.try_recv() {
+                    Ok(envelope) => envelope,
+                    Err(_) => return self.batch.pop_front(),
+                }
+            };
+
+            match envelope {
+                Envelope::Msg(msg) => return Some(msg),
+                Envelope::Batch(msgs) => self.batch.extend(msgs),
+                // Stop accepting messages, but handle the ones already queued
+                Envelope::PoisonPill => self.messages.close(),
+                Envelope::Kill => return None,
+            }
+        }
+    }
+
+    /// Turns whatever is left in a stopped actor's lanes into dead letters
+    fn drop_pending(&mut self, options: &ActorOptions<M>) {
+        for lane in [&mut self.control, &mut self.messages] {
+            lane.close();
+            while let Ok(envelope) = lane.try_recv() {
+                self.batch.extend(envelope.into_messages());
+            }
+        }
+
+        for message in self.batch.drain(..) {
+            options.dead_letter(message, DeadLetterReason::DroppedOnShutdown);
+        }
+    }
+}
+
+/// Why a message ended up in the dead letters
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadLetterReason {
+    // Told to an actor that had already stopped
+    ActorStopped,
+    // Still queued when the actor stopped without draining its mailbox
+    DroppedOnShutdown,
+    // Being handled when a supervised actor panicked
+    HandlerPanicked,
+}
+
+/// A message that was never handled
+#[derive(Debug)]
+pub struct DeadLetter<M> {
+    pub actor: String,
+    pub message: M,
+    pub reason: DeadLetterReason,
+}
+
+/// A message `try_tell` or `tell_timeout` could not deliver. It is handed back
+/// instead of becoming a dead letter, so the caller can retry or drop it.
+#[derive(Debug)]
+pub struct Undelivered<M> {
+    pub message: M,
+    pub error: ProcessorError,
+}
+
+impl<M> Undelivered<M> {
+    fn new(envelope: Envelope<M>, error: ProcessorError) -> Self {
+        let Envelope::Msg(message) = envelope else {
+            unreachable!("only single messages are handed back")
+        };
+        Self { message, error }
+    }
+}
+
+/// Collects the messages that could not be delivered to or handled by the actors it
+/// is registered with, so they can be inspected and replayed. Clones share the queue.
+pub struct DeadLetters<M> {
+    letters: Arc<Mutex<Vec<DeadLetter<M>>>>,
+    // Messages that are not kept are dropped instead
+    keep: fn(&M) -> bool,
+}
+
+impl<M> Clone for DeadLetters<M> {
+    fn clone(&self) -> Self {
+        Self {
+            letters: self.letters.clone(),
+            keep: self.keep,
+        }
+    }
+}
+
+impl<M> Default for DeadLetters<M> {
+    fn default() -> Self {
+        Self::keeping(|_| true)
+    }
+}
+
+impl<M> fmt::Debug for DeadLetters<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadLetters").field("len", &self.len()).finish()
+    }
+}
+
+impl<M> DeadLetters<M> {
+    /// Dead letters that only keep the messages `keep` accepts. Messages carrying a
+    /// reply sender should be dropped, so the asker sees the sender close instead of
+    /// waiting for as long as the letter is kept.
+    pub fn keeping(keep: fn(&M) -> bool) -> Self {
+        Self {
+            letters: Arc::new(Mutex::new(Vec::new())),
+            keep,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.letters.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes and returns the dead letters collected so far, oldest first
+    pub fn take(&self) -> Vec<DeadLetter<M>> {
+        std::mem::take(&mut *self.letters.lock().unwrap())
+    }
+
+    fn push(&self, actor: &str, message: M, reason: DeadLetterReason) {
+        if !(self.keep)(&message) {
+            return;
+        }
+
+        self.letters.lock().unwrap().push(DeadLetter {
+            actor: actor.to_string(),
+            message,
+            reason,
+        });
+    }
+}
+
+impl<M: Send> DeadLetters<M> {
+    /// Tells every dead letter to `actor` again, oldest first, and returns how many
+    /// were delivered. Stops at the first one that cannot be delivered; it and the
+    /// ones after it stay dead letters, as they were.
+    pub async fn replay(&self, actor: &ActorRef<M>) -> ProcessorResult<usize> {
+        let mut letters = self.take().into_iter();
+        let mut replayed = 0;
+        while let Some(DeadLetter {
+            actor: name,
+            message,
+            reason,
+        }) = letters.next()
+        {
+            // Sent past `actor`'s own dead letters, which may not be these
+            if let Err(envelope) = ActorRef::send_on(&actor.sender, Envelope::Msg(message)).await {
+                let mut queue = self.letters.lock().unwrap();
+                queue.extend(envelope.into_messages().into_iter().map(|message| DeadLetter {
+                    actor: name.clone(),
+                    message,
+                    reason,
+                }));
+                queue.extend(letters);
+                return Err(map_channel_send_err(TrySendError::Closed(())));
+            }
+            replayed += 1;
+        }
+
+        Ok(replayed)
+    }
+}
 
 #[derive(Debug)]
 pub struct ActorRef<M>
 where
     M: Send,
 {
-    sender: mpsc::Sender<M>,
+    sender: mpsc::Sender<Envelope<M>>,
+    control: mpsc::Sender<Envelope<M>>,
+    name: Arc<str>,
+    dead_letters: Option<DeadLetters<M>>,
 }
 
 impl<M: Send + 'static> Clone for ActorRef<M> {
     fn clone(&self) -> Self {
         Self {
             sender: self.sender.clone(),
+            control: self.control.clone(),
+            name: self.name.clone(),
+            dead_letters: self.dead_letters.clone(),
         }
     }
 }
 
 impl<M: Send> ActorRef<M> {
-    pub async fn tell(&self, mut msg: M) -> ProcessorResult<()> {
-        // We try indefinitely to send the message. We should add
-        // a timed variant of tell later.
-        loop {
-            match self.sender.try_send(msg) {
-                Ok(()) => return Ok(()),
-                Err(TrySendError::Full(m)) => {
-                    msg = m;
-                    tokio::time::sleep(Duration::from_millis(10)).await;
-                    eprintln!("Channel buffer full. Trying again")
-                }
-                Err(TrySendError::Closed(m)) => {
-                    return Err(map_channel_send_err(TrySendError::Closed(m)));
-                }
+    /// Waits for room in the actor's mailbox. Only fails once the actor has stopped.
+    pub async fn tell(&self, msg: M) -> ProcessorResult<()> {
+        self.send(Envelope::Msg(msg)).await
+    }
+
+    /// Sends `msgs` in a single channel operation. The actor handles them in order, as
+    /// if each had been told on its own.
+    pub async fn tell_batch(&self, msgs: Vec<M>) -> ProcessorResult<()> {
+        if msgs.is_empty() {
+            return Ok(());
+        }
+
+        self.send(Envelope::Batch(msgs)).await
+    }
+
+    /// Fails with `ActorMailboxFull` instead of waiting when the mailbox is full
+    pub fn try_tell(&self, msg: M) -> Result<(), Undelivered<M>> {
+        self.sender.try_send(Envelope::Msg(msg)).map_err(|e| match e {
+            TrySendError::Full(envelope) => Undelivered::new(envelope, map_channel_send_err(TrySendError::Full(()))),
+            TrySendError::Closed(envelope) => {
+                Undelivered::new(envelope, map_channel_send_err(TrySendError::Closed(())))
+            }
+        })
+    }
+
+    /// Waits at most `timeout` for room in the mailbox, then fails with `ActorTellTimeout`
+    pub async fn tell_timeout(&self, msg: M, timeout: Duration) -> Result<(), Undelivered<M>> {
+        self.sender
+            .send_timeout(Envelope::Msg(msg), timeout)
+            .await
+            .map_err(|e| match e {
+                SendTimeoutError::Timeout(envelope) => Undelivered::new(
+                    envelope,
+                    map_channel_send_timeout_err(SendTimeoutError::Timeout(()), timeout),
+                ),
+                SendTimeoutError::Closed(envelope) => Undelivered::new(
+                    envelope,
+                    map_channel_send_timeout_err(SendTimeoutError::Closed(()), timeout),
+                ),
+            })
+    }
+
+    /// Sends `msg` on the control lane, ahead of the messages waiting in the mailbox
+    pub async fn tell_control(&self, msg: M) -> ProcessorResult<()> {
+        Self::send_on(&self.control, Envelope::Msg(msg))
+            .await
+            .map_err(|envelope| self.stopped(envelope))
+    }
+
+    /// Asks the actor to stop once it has handled the messages already in its
+    /// mailbox. Messages told after this are rejected.
+    pub async fn stop(&self) -> ProcessorResult<()> {
+        self.send(Envelope::PoisonPill).await
+    }
+
+    /// Stops the actor as soon as it is done with the message at hand. The messages
+    /// still in its mailbox become dead letters.
+    pub async fn kill(&self) -> ProcessorResult<()> {
+        Self::send_on(&self.control, Envelope::Kill)
+            .await
+            .map_err(|envelope| self.stopped(envelope))
+    }
+
+    async fn send(&self, envelope: Envelope<M>) -> ProcessorResult<()> {
+        Self::send_on(&self.sender, envelope)
+            .await
+            .map_err(|envelope| self.stopped(envelope))
+    }
+
+    async fn send_on(lane: &mpsc::Sender<Envelope<M>>, envelope: Envelope<M>) -> Result<(), Envelope<M>> {
+        lane.send(envelope).await.map_err(|e| e.0)
+    }
+
+    fn stopped(&self, envelope: Envelope<M>) -> ProcessorError {
+        self.undeliverable(envelope, DeadLetterReason::ActorStopped);
+        map_channel_send_err(TrySendError::Closed(()))
+    }
+
+    /// The name the actor was started with
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn undeliverable(&self, envelope: Envelope<M>, reason: DeadLetterReason) {
+        if let Some(dead_letters) = &self.dead_letters {
+            for message in envelope.into_messages() {
+                dead_letters.push(&self.name, message, reason);
             }
         }
     }
@@ -51,6 +378,231 @@ impl<M: Send> ActorRef<M> {
         self.tell(msg).await?;
         response_channel.await.map_err(map_channel_recv_err)
     }
+
+    /// Ask pattern over the control lane, so the answer does not wait for the
+    /// messages queued in the mailbox
+    pub async fn ask_control<R>(&self, msg: M, response_channel: oneshot::Receiver<R>) -> ProcessorResult<R>
+    where
+        R: Send,
+    {
+        self.tell_control(msg).await?;
+        response_channel.await.map_err(map_channel_recv_err)
+    }
+
+    /// Ask pattern that creates the reply channel itself: `make_msg` builds the message
+    /// around the reply sender, e.g. `actor.ask_with(Message::Get, None)`. With a
+    /// `timeout`, fails with `ActorAskTimeout` when no reply came in time, waiting for
+    /// room in the mailbox included.
+    pub async fn ask_with<R, F>(&self, make_msg: F, timeout: Option<Duration>) -> ProcessorResult<R>
+    where
+        R: Send,
+        F: FnOnce(oneshot::Sender<R>) -> M,
+    {
+        let (tx, rx) = oneshot::channel();
+        Self::within(timeout, self.ask(make_msg(tx), rx)).await
+    }
+
+    /// `ask_with` over the control lane
+    pub async fn ask_control_with<R, F>(&self, make_msg: F, timeout: Option<Duration>) -> ProcessorResult<R>
+    where
+        R: Send,
+        F: FnOnce(oneshot::Sender<R>) -> M,
+    {
+        let (tx, rx) = oneshot::channel();
+        Self::within(timeout, self.ask_control(make_msg(tx), rx)).await
+    }
+
+    async fn within<R>(
+        timeout: Option<Duration>,
+        reply: impl Future<Output = ProcessorResult<R>>,
+    ) -> ProcessorResult<R> {
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, reply)
+                .await
+                .map_err(|_| ProcessorError::ActorAskTimeout { timeout })?,
+            None => reply.await,
+        }
+    }
+}
+
+/// Actors of any message type, reachable by the name they were started with. Clones
+/// share the registry, so it can be handed to actors to find each other.
+#[derive(Clone, Default)]
+pub struct ActorRegistry {
+    actors: Arc<RwLock<BTreeMap<String, Box<dyn Any + Send + Sync>>>>,
+}
+
+impl fmt::Debug for ActorRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ActorRegistry").field("actors", &self.names()).finish()
+    }
+}
+
+impl ActorRegistry {
+    /// Registers `actor_ref` under its name, which has to be unique in the registry
+    pub fn register<M>(&self, actor_ref: ActorRef<M>) -> ProcessorResult<()>
+    where
+        M: Send + 'static,
+    {
+        let mut actors = self.actors.write().unwrap();
+        let name = actor_ref.name().to_string();
+        if actors.contains_key(&name) {
+            return Err(ProcessorError::ActorAlreadyRegistered { name });
+        }
+
+        actors.insert(name, Box::new(actor_ref));
+        Ok(())
+    }
+
+    pub fn unregister(&self, name: &str) -> bool {
+        self.actors.write().unwrap().remove(name).is_some()
+    }
+
+    /// The actor registered as `name`, if there is one and it takes `M` messages
+    pub fn lookup<M>(&self, name: &str) -> Option<ActorRef<M>>
+    where
+        M: Send + 'static,
+    {
+        self.actors
+            .read()
+            .unwrap()
+            .get(name)
+            .and_then(|actor| actor.downcast_ref::<ActorRef<M>>())
+            .cloned()
+    }
+
+    /// Names of all the registered actors, in order
+    pub fn names(&self) -> Vec<String> {
+        self.actors.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Every registered actor taking `M` messages, in name order
+    pub fn actors_of<M>(&self) -> Vec<ActorRef<M>>
+    where
+        M: Send + 'static,
+    {
+        self.actors
+            .read()
+            .unwrap()
+            .values()
+            .filter_map(|actor| actor.downcast_ref::<ActorRef<M>>())
+            .cloned()
+            .collect()
+    }
+
+    /// Tells a message built by `make_msg` to every registered actor taking `M`
+    /// messages. Returns the actors it could not be delivered to, with the reason.
+    pub async fn broadcast<M, F>(&self, make_msg: F) -> Vec<(String, ProcessorError)>
+    where
+        M: Send + 'static,
+        F: Fn() -> M,
+    {
+        let mut failed = Vec::new();
+        for actor in self.actors_of::<M>() {
+            if let Err(e) = actor.tell(make_msg()).await {
+                failed.push((actor.name().to_string(), e));
+            }
+        }
+
+        failed
+    }
+
+    /// `scatter_gather` to every registered actor taking `M` messages
+    pub async fn scatter_gather<M, R, F>(&self, make_msg: F, timeout: Option<Duration>) -> Gathered<R>
+    where
+        M: Send + 'static,
+        R: Send,
+        F: Fn(oneshot::Sender<R>) -> M,
+    {
+        scatter_gather(&self.actors_of::<M>(), make_msg, timeout).await
+    }
+}
+
+/// Replies collected from a set of actors, in the order the actors were given
+#[derive(Debug)]
+pub struct Gathered<R> {
+    // Actor name and its reply
+    pub replies: Vec<(String, R)>,
+    // Actor name and why it did not reply
+    pub failures: Vec<(String, ProcessorError)>,
+}
+
+impl<R> Gathered<R> {
+    /// Every actor replied
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// The replies if every actor replied, otherwise `MissingReplies` naming the others
+    pub fn complete(self) -> ProcessorResult<Vec<(String, R)>> {
+        if self.is_complete() {
+            return Ok(self.replies);
+        }
+
+        let actors: Vec<String> = self
+            .failures
+            .iter()
+            .map(|(actor, e)| format!("{} ({})", actor, e))
+            .collect();
+        Err(ProcessorError::MissingReplies {
+            actors: actors.join(", "),
+        })
+    }
+}
+
+/// Asks all `actors` at once, with a message built by `make_msg` around each reply
+/// sender, and collects the replies. With a `timeout`, actors that did not reply in
+/// time are reported in the failures together with the stopped ones.
+pub async fn scatter_gather<'a, M, R, F>(
+    actors: impl IntoIterator<Item = &'a ActorRef<M>>,
+    make_msg: F,
+    timeout: Option<Duration>,
+) -> Gathered<R>
+where
+    M: Send + 'a,
+    R: Send,
+    F: Fn(oneshot::Sender<R>) -> M,
+{
+    gather(actors, |actor| actor.ask_with(&make_msg, timeout)).await
+}
+
+/// `scatter_gather` over the control lane, ahead of the queued messages
+pub async fn scatter_gather_control<'a, M, R, F>(
+    actors: impl IntoIterator<Item = &'a ActorRef<M>>,
+    make_msg: F,
+    timeout: Option<Duration>,
+) -> Gathered<R>
+where
+    M: Send + 'a,
+    R: Send,
+    F: Fn(oneshot::Sender<R>) -> M,
+{
+    gather(actors, |actor| actor.ask_control_with(&make_msg, timeout)).await
+}
+
+async fn gather<'a, M, R, Fut>(
+    actors: impl IntoIterator<Item = &'a ActorRef<M>>,
+    ask: impl Fn(&'a ActorRef<M>) -> Fut,
+) -> Gathered<R>
+where
+    M: Send + 'a,
+    Fut: Future<Output = ProcessorResult<R>>,
+{
+    let actors: Vec<_> = actors.into_iter().collect();
+    let results = future::join_all(actors.iter().map(|actor| ask(actor))).await;
+
+    let mut gathered = Gathered {
+        replies: Vec::new(),
+        failures: Vec::new(),
+    };
+    for (actor, result) in actors.into_iter().zip(results) {
+        match result {
+            Ok(reply) => gathered.replies.push((actor.name().to_string(), reply)),
+            Err(e) => gathered.failures.push((actor.name().to_string(), e)),
+        }
+    }
+
+    gathered
 }
 
 /// Actors implement this trait for their message type.
@@ -60,39 +612,335 @@ where
     M: Send,
 {
     async fn handle(&mut self, msg: M) -> ProcessorResult<()>;
+
+    /// Errors hit besides the one returned by `handle`, e.g. for messages the actor
+    /// held back and applied later. Taken and reported after every message.
+    fn take_errors(&mut self) -> Vec<(M, ProcessorError)> {
+        Vec::new()
+    }
+
+    /// Copy of `msg` to report along with the error handling it fails with. Only
+    /// taken when the actor has an error sink; errors are reported without one otherwise.
+    fn error_context(_msg: &M) -> Option<M>
+    where
+        Self: Sized,
+    {
+        None
+    }
+}
+
+/// Actors that can be restarted by a supervisor. The supervisor keeps a clone of the
+/// actor as its snapshot, and a journal of the messages handled since, to rebuild the
+/// state the actor had right before it failed.
+pub trait Recoverable<M>: ChannelActor<M> + Clone
+where
+    M: Send,
+{
+    /// Copy of `msg` to replay after a restart. Messages without one are followed by a
+    /// fresh snapshot, so they are never replayed. `msg` can first be given what the
+    /// replay needs to decide the same way, e.g. the time it is handled at.
+    fn journal(msg: &mut M) -> Option<M>;
+}
+
+/// How a supervised actor is restarted. Restarts are one-for-one: a failed actor is
+/// restarted on its own while the other actors keep running.
+#[derive(Clone, Debug)]
+pub struct Supervision {
+    // The actor is given up on when it fails more than `max_restarts` times within `window`
+    pub max_restarts: usize,
+    pub window: Duration,
+    // Number of journaled messages after which a new snapshot is taken
+    pub snapshot_every: usize,
+}
+
+impl Default for Supervision {
+    fn default() -> Self {
+        Self {
+            max_restarts: 3,
+            window: Duration::from_secs(60),
+            snapshot_every: 10_000,
+        }
+    }
+}
+
+/// A panic of a supervised actor while handling a message
+#[derive(Clone, Debug)]
+pub struct ActorFailure {
+    // Name of the actor that failed
+    pub actor: String,
+    pub reason: String,
+    // False once the actor has used up its restarts and was stopped
+    pub restarted: bool,
+}
+
+/// An error returned by an actor's handler
+#[derive(Debug)]
+pub struct ActorError<M> {
+    // Name of the actor that failed
+    pub actor: String,
+    // The message being handled, when the actor gives a copy of it for reports
+    pub message: Option<M>,
+    pub error: ProcessorError,
+}
+
+/// Where a started actor reports its handler errors
+pub type ErrorSink<M> = mpsc::UnboundedSender<ActorError<M>>;
+
+/// How an actor identifies itself and where its errors and undelivered messages go
+#[derive(Debug)]
+pub struct ActorOptions<M> {
+    pub name: String,
+    // Handler errors are dropped when no sink is registered
+    pub errors: Option<ErrorSink<M>>,
+    // Undelivered messages are dropped when no dead letters are registered
+    pub dead_letters: Option<DeadLetters<M>>,
+}
+
+impl<M> Clone for ActorOptions<M> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            errors: self.errors.clone(),
+            dead_letters: self.dead_letters.clone(),
+        }
+    }
+}
+
+impl<M> Default for ActorOptions<M> {
+    fn default() -> Self {
+        Self::new("actor")
+    }
 }
 
-/// Start an actor with bounded buffer size.
-/// Spawns the actor loop on a tokio task and returns an `ActorRef`.
-pub async fn start<A, M>(mut actor_instance: A, buf_size: usize) -> ActorRef<M>
+impl<M> ActorOptions<M> {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            errors: None,
+            dead_letters: None,
+        }
+    }
+
+    pub fn with_errors(mut self, errors: ErrorSink<M>) -> Self {
+        self.errors = Some(errors);
+        self
+    }
+
+    pub fn with_dead_letters(mut self, dead_letters: DeadLetters<M>) -> Self {
+        self.dead_letters = Some(dead_letters);
+        self
+    }
+
+    fn dead_letter(&self, message: M, reason: DeadLetterReason) {
+        if let Some(dead_letters) = &self.dead_letters {
+            dead_letters.push(&self.name, message, reason);
+        }
+    }
+
+    /// Copy of `msg` for the error report, only taken when there is a sink to report to
+    fn context<A>(&self, msg: &M) -> Option<M>
+    where
+        M: Send,
+        A: ChannelActor<M>,
+    {
+        self.errors.as_ref().and_then(|_| A::error_context(msg))
+    }
+
+    fn report(&self, message: Option<M>, error: ProcessorError) {
+        if let Some(errors) = &self.errors {
+            let _ = errors.send(ActorError {
+                actor: self.name.clone(),
+                message,
+                error,
+            });
+        }
+    }
+
+    fn report_taken<A>(&self, actor_instance: &mut A)
+    where
+        M: Send,
+        A: ChannelActor<M>,
+    {
+        for (message, error) in actor_instance.take_errors() {
+            self.report(Some(message), error);
+        }
+    }
+}
+
+/// A running actor: the reference to talk to it and the task it runs on
+pub struct ActorHandle<A, M>
+where
+    M: Send,
+{
+    actor_ref: ActorRef<M>,
+    join_handle: JoinHandle<A>,
+}
+
+impl<A, M: Send> ActorHandle<A, M> {
+    pub fn actor_ref(&self) -> &ActorRef<M> {
+        &self.actor_ref
+    }
+
+    /// Stops the actor after it has drained its mailbox and returns its final state
+    pub async fn stop(self) -> ProcessorResult<A> {
+        // The actor may have stopped on its own already, the join tells how it went
+        let _ = self.actor_ref.stop().await;
+        self.join().await
+    }
+
+    /// Stops the actor without handling the messages still in its mailbox and returns
+    /// its final state
+    pub async fn kill(self) -> ProcessorResult<A> {
+        let _ = self.actor_ref.kill().await;
+        self.join().await
+    }
+
+    /// Waits for the actor to stop and returns its final state
+    pub async fn join(self) -> ProcessorResult<A> {
+        self.join_handle
+            .await
+            .map_err(|e| ProcessorError::ActorJoinError(e.to_string()))
+    }
+}
+
+/// Start an actor with bounded buffer size and default options.
+/// Spawns the actor loop on a tokio task and returns an `ActorHandle`.
+pub async fn start<A, M>(actor_instance: A, buf_size: usize) -> ActorHandle<A, M>
 where
     M: Send + 'static,
     A: ChannelActor<M> + Send + 'static,
 {
-    let (tx, mut rx) = mpsc::channel(buf_size);
-    let actor_ref = ActorRef { sender: tx };
-
-    let _join_handle = tokio::spawn(async move {
-        // Run the actor indefinitely
-        while let Some(msg) = rx.recv().await {
-            if let Err(err) = actor_instance.handle(msg).await {
-                // Not the right way to kill an actor. Ideally, we should have
-                // an explicit PoisonPill message sent to self and then exit
-                match err {
-                    ProcessorError::FatalError => {
-                        break;
+    start_with(actor_instance, buf_size, ActorOptions::default()).await
+}
+
+/// Start an actor with bounded buffer size, reporting its errors as set in `options`
+pub async fn start_with<A, M>(mut actor_instance: A, buf_size: usize, options: ActorOptions<M>) -> ActorHandle<A, M>
+where
+    M: Send + 'static,
+    A: ChannelActor<M> + Send + 'static,
+{
+    let (actor_ref, mut mailbox) = Mailbox::channel(buf_size, &options);
+
+    let join_handle = tokio::spawn(async move {
+        // Run the actor until it is stopped or every ActorRef is dropped
+        while let Some(msg) = mailbox.next().await {
+            let context = options.context::<A>(&msg);
+            let result = actor_instance.handle(msg).await;
+            options.report_taken(&mut actor_instance);
+            match result {
+                Ok(()) => {}
+                // A fatal error stops the actor right away, without draining its mailbox
+                Err(ProcessorError::FatalError) => {
+                    options.report(context, ProcessorError::FatalError);
+                    break;
+                }
+                Err(err) => options.report(context, err),
+            }
+        }
+
+        mailbox.drop_pending(&options);
+        actor_instance
+    });
+
+    ActorHandle { actor_ref, join_handle }
+}
+
+/// Start an actor under `supervision`. A panic while handling a message drops that
+/// message and restarts the actor from its last snapshot, replaying the journal on top
+/// of it. Every failure is reported on `failures`, handler errors as set in `options`.
+pub async fn start_supervised<A, M>(
+    actor_instance: A,
+    buf_size: usize,
+    options: ActorOptions<M>,
+    supervision: Supervision,
+    failures: mpsc::UnboundedSender<ActorFailure>,
+) -> ActorHandle<A, M>
+where
+    M: Send + 'static,
+    A: Recoverable<M> + Send + 'static,
+{
+    let (actor_ref, mut mailbox) = Mailbox::channel(buf_size, &options);
+
+    let join_handle = tokio::spawn(async move {
+        let mut actor_instance = actor_instance;
+        let mut snapshot = actor_instance.clone();
+        let mut journal = Vec::new();
+        let mut restarts = VecDeque::new();
+
+        while let Some(mut msg) = mailbox.next().await {
+            let entry = A::journal(&mut msg);
+            let context = options.context::<A>(&msg);
+            match AssertUnwindSafe(actor_instance.handle(msg)).catch_unwind().await {
+                Ok(result) => {
+                    options.report_taken(&mut actor_instance);
+                    match result {
+                        Ok(()) => {}
+                        Err(ProcessorError::FatalError) => {
+                            options.report(context, ProcessorError::FatalError);
+                            break;
+                        }
+                        Err(err) => options.report(context, err),
+                    }
+
+                    match entry {
+                        Some(entry) if journal.len() + 1 < supervision.snapshot_every => journal.push(entry),
+                        _ => {
+                            snapshot = actor_instance.clone();
+                            journal.clear();
+                        }
                     }
-                    _ => {
-                        // Commenting out this so that test outputs do not get polluted;
-                        // ToDo: Log errors to file
-                        // eprintln!("Actor error: {:?}", err);
+                }
+                Err(panic) => {
+                    let now = Instant::now();
+                    while restarts
+                        .front()
+                        .is_some_and(|at| now.duration_since(*at) > supervision.window)
+                    {
+                        restarts.pop_front();
+                    }
+                    let restarted = restarts.len() < supervision.max_restarts;
+                    let _ = failures.send(ActorFailure {
+                        actor: options.name.clone(),
+                        reason: panic_reason(panic),
+                        restarted,
+                    });
+                    // Only journaled messages can be kept, the others were consumed
+                    if let Some(entry) = entry {
+                        options.dead_letter(entry, DeadLetterReason::HandlerPanicked);
+                    }
+
+                    // Rebuild the state from before the failed message
+                    actor_instance = snapshot.clone();
+                    for entry in journal.drain(..) {
+                        let _ = actor_instance.handle(entry).await;
+                    }
+                    // Those were reported when the entries were first handled
+                    let _ = actor_instance.take_errors();
+                    snapshot = actor_instance.clone();
+
+                    if !restarted {
+                        break;
                     }
+                    restarts.push_back(now);
                 }
             }
         }
+
+        mailbox.drop_pending(&options);
+        actor_instance
     });
 
-    actor_ref
+    ActorHandle { actor_ref, join_handle }
+}
+
+fn panic_reason(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(reason) => *reason,
+        Err(panic) => panic
+            .downcast_ref::<&str>()
+            .map(|reason| reason.to_string())
+            .unwrap_or_else(|| "unknown panic".to_string()),
+    }
 }
 
 #[cfg(test)]
@@ -110,6 +958,7 @@ mod tests {
     enum TestMessage {
         Store(i32),
         Get(oneshot::Sender<Vec<i32>>),
+        Panic,
     }
 
     #[async_trait::async_trait]
@@ -123,15 +972,50 @@ mod tests {
                     let snapshot = self.state.lock().unwrap().clone();
                     let _ = tx.send(snapshot);
                 }
+                TestMessage::Panic => panic!("test actor panicked"),
+            }
+            Ok(())
+        }
+    }
+
+    // test actor owning its state, so that it can be restored from a snapshot
+    #[derive(Clone, Default)]
+    struct RecoverableActor {
+        state: Vec<i32>,
+    }
+
+    #[async_trait::async_trait]
+    impl ChannelActor<TestMessage> for RecoverableActor {
+        async fn handle(&mut self, msg: TestMessage) -> ProcessorResult<()> {
+            match msg {
+                TestMessage::Store(v) => self.state.push(v),
+                TestMessage::Get(tx) => {
+                    let _ = tx.send(self.state.clone());
+                }
+                TestMessage::Panic => {
+                    // Corrupt the state before failing, the restart has to undo this
+                    self.state.clear();
+                    panic!("test actor panicked");
+                }
             }
             Ok(())
         }
     }
 
+    impl Recoverable<TestMessage> for RecoverableActor {
+        fn journal(msg: &mut TestMessage) -> Option<TestMessage> {
+            match msg {
+                TestMessage::Store(v) => Some(TestMessage::Store(*v)),
+                _ => None,
+            }
+        }
+    }
+
     #[tokio::test]
     async fn test_tell_updates_state() {
         let state = Arc::new(Mutex::new(Vec::new()));
-        let actor = start(TestActor { state: state.clone() }, 8).await;
+        let handle = start(TestActor { state: state.clone() }, 8).await;
+        let actor = handle.actor_ref();
 
         actor.tell(TestMessage::Store(10)).await.unwrap();
         actor.tell(TestMessage::Store(20)).await.unwrap();
@@ -148,7 +1032,8 @@ mod tests {
     #[tokio::test]
     async fn test_ask_returns_state() {
         let state = Arc::new(Mutex::new(Vec::new()));
-        let actor = start(TestActor { state: state.clone() }, 8).await;
+        let handle = start(TestActor { state: state.clone() }, 8).await;
+        let actor = handle.actor_ref();
 
         actor.tell(TestMessage::Store(42)).await.unwrap();
 
@@ -159,4 +1044,440 @@ mod tests {
             .map(|result| assert_eq!(result, vec![42]))
             .unwrap();
     }
+
+    #[tokio::test]
+    async fn test_full_mailbox_errors() {
+        // An actor that never gets to run keeps its mailbox full
+        let (actor, mailbox) = Mailbox::channel(1, &ActorOptions::default());
+
+        actor.try_tell(TestMessage::Store(1)).unwrap();
+        assert!(matches!(
+            actor.try_tell(TestMessage::Store(2)),
+            Err(Undelivered {
+                message: TestMessage::Store(2),
+                error: ProcessorError::ActorMailboxFull
+            })
+        ));
+        assert!(matches!(
+            actor
+                .tell_timeout(TestMessage::Store(3), Duration::from_millis(10))
+                .await,
+            Err(Undelivered {
+                message: TestMessage::Store(3),
+                error: ProcessorError::ActorTellTimeout { .. }
+            })
+        ));
+
+        drop(mailbox);
+        assert!(matches!(
+            actor.tell(TestMessage::Store(3)).await,
+            Err(ProcessorError::ActorTxSendError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stop_drains_mailbox_and_returns_state() {
+        let state = Arc::new(Mutex::new(Vec::new()));
+        let handle = start(TestActor { state: state.clone() }, 8).await;
+        let actor = handle.actor_ref().clone();
+
+        actor.tell(TestMessage::Store(1)).await.unwrap();
+        actor.tell(TestMessage::Store(2)).await.unwrap();
+
+        let stopped = handle.stop().await.unwrap();
+        assert_eq!(*stopped.state.lock().unwrap(), vec![1, 2]);
+        assert!(actor.tell(TestMessage::Store(3)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_supervised_actor_restarts_from_snapshot() {
+        let supervision = Supervision {
+            snapshot_every: 2,
+            ..Default::default()
+        };
+        let (failures_tx, mut failures) = mpsc::unbounded_channel();
+        let handle = start_supervised(
+            RecoverableActor::default(),
+            8,
+            ActorOptions::new("recoverable"),
+            supervision,
+            failures_tx,
+        )
+        .await;
+        let actor = handle.actor_ref();
+
+        for v in 1..=3 {
+            actor.tell(TestMessage::Store(v)).await.unwrap();
+        }
+        actor.tell(TestMessage::Panic).await.unwrap();
+        actor.tell(TestMessage::Store(4)).await.unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        assert_eq!(actor.ask(TestMessage::Get(tx), rx).await.unwrap(), vec![1, 2, 3, 4]);
+
+        let failure = failures.recv().await.unwrap();
+        assert_eq!(failure.actor, "recoverable");
+        assert_eq!(failure.reason, "test actor panicked");
+        assert!(failure.restarted);
+    }
+
+    #[tokio::test]
+    async fn test_supervised_actor_gives_up_after_max_restarts() {
+        let supervision = Supervision {
+            max_restarts: 1,
+            ..Default::default()
+        };
+        let (failures_tx, mut failures) = mpsc::unbounded_channel();
+        let handle = start_supervised(
+            RecoverableActor::default(),
+            8,
+            ActorOptions::default(),
+            supervision,
+            failures_tx,
+        )
+        .await;
+        let actor = handle.actor_ref();
+
+        actor.tell(TestMessage::Store(1)).await.unwrap();
+        actor.tell(TestMessage::Panic).await.unwrap();
+        actor.tell(TestMessage::Panic).await.unwrap();
+
+        let stopped = handle.join().await.unwrap();
+        assert_eq!(stopped.state, vec![1]);
+        assert!(failures.recv().await.unwrap().restarted);
+        assert!(!failures.recv().await.unwrap().restarted);
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum FailingMessage {
+        Fail(u32),
+    }
+
+    #[derive(Clone)]
+    struct FailingActor;
+
+    #[async_trait::async_trait]
+    impl ChannelActor<FailingMessage> for FailingActor {
+        async fn handle(&mut self, msg: FailingMessage) -> ProcessorResult<()> {
+            let FailingMessage::Fail(tx_id) = msg;
+            Err(ProcessorError::TransactionNotFound { tx_id: tx_id.into() })
+        }
+
+        fn error_context(msg: &FailingMessage) -> Option<FailingMessage> {
+            Some(msg.clone())
+        }
+    }
+
+    impl Recoverable<FailingMessage> for FailingActor {
+        fn journal(msg: &mut FailingMessage) -> Option<FailingMessage> {
+            Some(msg.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_reported_with_actor_and_message() {
+        let (errors_tx, mut errors) = mpsc::unbounded_channel();
+        let options = ActorOptions::new("failing").with_errors(errors_tx);
+        let handle = start_with(FailingActor, 8, options).await;
+
+        handle.actor_ref().tell(FailingMessage::Fail(7)).await.unwrap();
+        handle.stop().await.unwrap();
+
+        let error = errors.recv().await.unwrap();
+        assert_eq!(error.actor, "failing");
+        assert_eq!(error.message, Some(FailingMessage::Fail(7)));
+        assert!(matches!(error.error, ProcessorError::TransactionNotFound { tx_id: 7 }));
+    }
+
+    #[tokio::test]
+    async fn test_supervised_errors_are_reported_with_message() {
+        let (errors_tx, mut errors) = mpsc::unbounded_channel();
+        let (failures_tx, _failures) = mpsc::unbounded_channel();
+        let options = ActorOptions::new("failing").with_errors(errors_tx);
+        let handle = start_supervised(FailingActor, 8, options, Supervision::default(), failures_tx).await;
+
+        handle.actor_ref().tell(FailingMessage::Fail(7)).await.unwrap();
+        handle.stop().await.unwrap();
+
+        let error = errors.recv().await.unwrap();
+        assert_eq!(error.actor, "failing");
+        assert_eq!(error.message, Some(FailingMessage::Fail(7)));
+    }
+
+    #[tokio::test]
+    async fn test_undelivered_messages_become_dead_letters() {
+        let state = Arc::new(Mutex::new(Vec::new()));
+        let dead_letters = DeadLetters::default();
+        let options = ActorOptions::new("stopped").with_dead_letters(dead_letters.clone());
+        let handle = start_with(TestActor { state: state.clone() }, 8, options).await;
+        let actor = handle.actor_ref().clone();
+        handle.stop().await.unwrap();
+
+        assert!(actor.tell(TestMessage::Store(1)).await.is_err());
+        assert!(actor.tell(TestMessage::Store(2)).await.is_err());
+        // Handed back to the caller instead
+        assert!(matches!(
+            actor.try_tell(TestMessage::Store(3)),
+            Err(Undelivered {
+                message: TestMessage::Store(3),
+                ..
+            })
+        ));
+
+        let letters = dead_letters.take();
+        assert_eq!(letters.len(), 2);
+        assert_eq!(letters[0].actor, "stopped");
+        assert_eq!(letters[0].reason, DeadLetterReason::ActorStopped);
+        assert!(matches!(letters[1].message, TestMessage::Store(2)));
+
+        // Replay them to a running actor
+        for letter in letters {
+            dead_letters.push(&letter.actor, letter.message, letter.reason);
+        }
+        let handle = start(TestActor { state: state.clone() }, 8).await;
+        assert_eq!(dead_letters.replay(handle.actor_ref()).await.unwrap(), 2);
+        handle.stop().await.unwrap();
+        assert_eq!(*state.lock().unwrap(), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_replay_keeps_the_dead_letters() {
+        let dead_letters = DeadLetters::default();
+        for v in 1..=2 {
+            dead_letters.push("gone", TestMessage::Store(v), DeadLetterReason::ActorStopped);
+        }
+
+        // The target has no dead letters of its own to catch the failed letter
+        let state = Arc::new(Mutex::new(Vec::new()));
+        let handle = start(TestActor { state }, 8).await;
+        let actor = handle.actor_ref().clone();
+        handle.stop().await.unwrap();
+
+        assert!(dead_letters.replay(&actor).await.is_err());
+        let letters = dead_letters.take();
+        assert_eq!(letters.len(), 2);
+        assert_eq!(letters[0].actor, "gone");
+        assert!(matches!(letters[0].message, TestMessage::Store(1)));
+        assert!(matches!(letters[1].message, TestMessage::Store(2)));
+    }
+
+    // Stops on the first message it handles
+    struct FatalActor;
+
+    #[async_trait::async_trait]
+    impl ChannelActor<TestMessage> for FatalActor {
+        async fn handle(&mut self, _msg: TestMessage) -> ProcessorResult<()> {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Err(ProcessorError::FatalError)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pending_messages_are_dead_letters_after_fatal_error() {
+        let dead_letters = DeadLetters::default();
+        let options = ActorOptions::new("fatal").with_dead_letters(dead_letters.clone());
+        let handle = start_with(FatalActor, 8, options).await;
+
+        for v in 1..=3 {
+            handle.actor_ref().tell(TestMessage::Store(v)).await.unwrap();
+        }
+        handle.join().await.unwrap();
+
+        let letters = dead_letters.take();
+        assert_eq!(letters.len(), 2);
+        assert!(
+            letters
+                .iter()
+                .all(|letter| letter.reason == DeadLetterReason::DroppedOnShutdown)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_asks_that_are_not_kept_fail_instead_of_waiting() {
+        let dead_letters = DeadLetters::keeping(|msg| matches!(msg, TestMessage::Store(_)));
+        let options = ActorOptions::new("fatal").with_dead_letters(dead_letters.clone());
+        let handle = start_with(FatalActor, 8, options).await;
+        let actor = handle.actor_ref().clone();
+
+        for v in 1..=2 {
+            actor.tell(TestMessage::Store(v)).await.unwrap();
+        }
+        // Queued behind the message that stops the actor
+        let (tx, rx) = oneshot::channel();
+        assert!(actor.ask(TestMessage::Get(tx), rx).await.is_err());
+        handle.join().await.unwrap();
+
+        let letters = dead_letters.take();
+        assert_eq!(letters.len(), 1);
+        assert!(matches!(letters[0].message, TestMessage::Store(2)));
+    }
+
+    #[tokio::test]
+    async fn test_control_lane_overtakes_queued_messages() {
+        // Blocks on its first message until released, so the mailbox fills up behind it
+        struct BlockedActor {
+            started: Option<oneshot::Sender<()>>,
+            release: Option<oneshot::Receiver<()>>,
+            handled: Vec<i32>,
+        }
+
+        #[async_trait::async_trait]
+        impl ChannelActor<TestMessage> for BlockedActor {
+            async fn handle(&mut self, msg: TestMessage) -> ProcessorResult<()> {
+                if let Some(started) = self.started.take() {
+                    let _ = started.send(());
+                }
+                if let Some(release) = self.release.take() {
+                    let _ = release.await;
+                }
+                match msg {
+                    TestMessage::Store(v) => self.handled.push(v),
+                    TestMessage::Get(tx) => {
+                        let _ = tx.send(self.handled.clone());
+                    }
+                    TestMessage::Panic => {}
+                }
+                Ok(())
+            }
+        }
+
+        let (started_tx, started) = oneshot::channel();
+        let (release, blocked) = oneshot::channel();
+        let actor = BlockedActor {
+            started: Some(started_tx),
+            release: Some(blocked),
+            handled: Vec::new(),
+        };
+        let handle = start(actor, 8).await;
+        let actor_ref = handle.actor_ref().clone();
+
+        for v in 1..=4 {
+            actor_ref.tell(TestMessage::Store(v)).await.unwrap();
+        }
+        // The query is queued on the control lane while the actor is blocked on the first message
+        started.await.unwrap();
+        let (tx, rx) = oneshot::channel();
+        actor_ref.tell_control(TestMessage::Get(tx)).await.unwrap();
+        release.send(()).unwrap();
+
+        // Only the message being handled when the query arrived was applied before it
+        assert_eq!(rx.await.unwrap(), vec![1]);
+
+        handle.kill().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_batch_is_handled_in_order() {
+        let state = Arc::new(Mutex::new(Vec::new()));
+        let handle = start(TestActor { state: state.clone() }, 1).await;
+
+        let actor = handle.actor_ref();
+        actor
+            .tell_batch((1..=5).map(TestMessage::Store).collect())
+            .await
+            .unwrap();
+        actor.tell(TestMessage::Store(6)).await.unwrap();
+        handle.stop().await.unwrap();
+
+        assert_eq!(*state.lock().unwrap(), vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[tokio::test]
+    async fn test_ask_with_builds_the_reply_channel() {
+        let state = Arc::new(Mutex::new(Vec::new()));
+        let handle = start(TestActor { state: state.clone() }, 8).await;
+        let actor = handle.actor_ref();
+
+        actor.tell(TestMessage::Store(7)).await.unwrap();
+        assert_eq!(actor.ask_with(TestMessage::Get, None).await.unwrap(), vec![7]);
+        assert_eq!(
+            actor
+                .ask_control_with(TestMessage::Get, Some(Duration::from_secs(1)))
+                .await
+                .unwrap(),
+            vec![7]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ask_with_times_out() {
+        // Nobody handles the mailbox, so the reply never comes
+        let (actor, _mailbox) = Mailbox::channel(8, &ActorOptions::default());
+
+        let reply: ProcessorResult<Vec<i32>> = actor.ask_with(TestMessage::Get, Some(Duration::from_millis(10))).await;
+        assert!(matches!(reply, Err(ProcessorError::ActorAskTimeout { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_registry_lookup_and_broadcast() {
+        let registry = ActorRegistry::default();
+        let state = Arc::new(Mutex::new(Vec::new()));
+        let mut handles = Vec::new();
+        for name in ["b", "a"] {
+            let options = ActorOptions::new(name);
+            let handle = start_with(TestActor { state: state.clone() }, 8, options).await;
+            registry.register(handle.actor_ref().clone()).unwrap();
+            handles.push(handle);
+        }
+
+        let (other, _mailbox) = Mailbox::<FailingMessage>::channel(8, &ActorOptions::new("other"));
+        registry.register(other).unwrap();
+        let (duplicate, _mailbox) = Mailbox::<TestMessage>::channel(8, &ActorOptions::new("a"));
+        assert!(matches!(
+            registry.register(duplicate),
+            Err(ProcessorError::ActorAlreadyRegistered { .. })
+        ));
+
+        assert_eq!(registry.names(), vec!["a", "b", "other"]);
+        assert!(registry.lookup::<TestMessage>("a").is_some());
+        assert!(registry.lookup::<TestMessage>("other").is_none());
+        assert_eq!(registry.actors_of::<TestMessage>().len(), 2);
+
+        assert!(registry.broadcast(|| TestMessage::Store(1)).await.is_empty());
+        for handle in handles {
+            handle.stop().await.unwrap();
+        }
+        assert_eq!(*state.lock().unwrap(), vec![1, 1]);
+
+        // Stopped actors stay registered until they are unregistered
+        let failed = registry.broadcast(|| TestMessage::Store(2)).await;
+        assert_eq!(failed.len(), 2);
+        assert!(registry.unregister("a"));
+        assert_eq!(registry.names(), vec!["b", "other"]);
+    }
+
+    #[tokio::test]
+    async fn test_scatter_gather_reports_partial_failures() {
+        let running = start_with(TestActor { state: Arc::default() }, 8, ActorOptions::new("running")).await;
+        running.actor_ref().tell(TestMessage::Store(1)).await.unwrap();
+
+        let stopped = start_with(TestActor { state: Arc::default() }, 8, ActorOptions::new("stopped")).await;
+        let stopped_ref = stopped.actor_ref().clone();
+        stopped.stop().await.unwrap();
+
+        // Nobody reads this mailbox, so the ask never gets a reply
+        let (stuck, _mailbox) = Mailbox::channel(8, &ActorOptions::new("stuck"));
+
+        let actors = [running.actor_ref().clone(), stopped_ref, stuck];
+        let timeout = Duration::from_millis(20);
+        let gathered = scatter_gather(&actors, TestMessage::Get, Some(timeout)).await;
+
+        assert!(!gathered.is_complete());
+        assert_eq!(gathered.replies, vec![("running".to_string(), vec![1])]);
+        assert_eq!(gathered.failures.len(), 2);
+        assert_eq!(gathered.failures[0].0, "stopped");
+        assert!(matches!(
+            gathered.failures[1],
+            (_, ProcessorError::ActorAskTimeout { .. })
+        ));
+        assert!(matches!(
+            gathered.complete(),
+            Err(ProcessorError::MissingReplies { actors }) if actors.starts_with("stopped (") && actors.contains(", stuck (")
+        ));
+
+        let gathered = scatter_gather_control(&actors[..1], TestMessage::Get, None).await;
+        assert!(gathered.is_complete());
+        assert_eq!(gathered.complete().unwrap(), vec![("running".to_string(), vec![1])]);
+        running.stop().await.unwrap();
+    }
 }
@@ -0,0 +1,246 @@
+use std::{collections::HashMap, io::Cursor, path::Path};
+
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use csv_async::{AsyncReaderBuilder, StringRecord, Trim};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+
+use crate::{CsvStreamReader, ProcessorResult};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// An input stream ready to be handed to the CSV deserializer
+pub type InputStream = Box<dyn AsyncRead + Unpin + Send>;
+
+/// Compression formats the input may come in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the format from the first bytes of the stream, falling back to the
+    /// file extension when the stream is too short to tell
+    pub fn detect(magic: &[u8], path: Option<&Path>) -> Self {
+        if magic.starts_with(&GZIP_MAGIC) {
+            return Compression::Gzip;
+        }
+        if magic.starts_with(&ZSTD_MAGIC) {
+            return Compression::Zstd;
+        }
+        // Anything long enough to carry a magic number but without one is plain text
+        if magic.len() >= ZSTD_MAGIC.len() {
+            return Compression::None;
+        }
+
+        match path.and_then(|path| path.extension()).and_then(|ext| ext.to_str()) {
+            Some("gz" | "gzip") => Compression::Gzip,
+            Some("zst" | "zstd") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// Wraps `reader` in a streaming decoder when its content is compressed, so large
+/// archives never have to be decompressed to disk first
+pub async fn decompress<R>(reader: R, path: Option<&Path>) -> ProcessorResult<InputStream>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let mut reader = BufReader::new(reader);
+    let compression = Compression::detect(peek(&mut reader).await?, path);
+
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => {
+            let mut decoder = GzipDecoder::new(reader);
+            // Concatenated archives are common when files are appended to over time
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+        Compression::Zstd => {
+            let mut decoder = ZstdDecoder::new(reader);
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+    })
+}
+
+/// How a feed lays out its CSV files. The default is the processor's own format:
+/// comma separated, quoted with `"`, with a `type,client,tx,amount` header.
+#[derive(Clone, Debug)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    pub quoting: bool,
+    pub has_headers: bool,
+    // Column names of headerless files, in the order they appear
+    pub columns: Vec<String>,
+    // Column name in the feed -> column name the processor expects
+    pub column_names: HashMap<String, String>,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            quoting: true,
+            has_headers: true,
+            columns: ["type", "client", "tx", "amount"].map(String::from).to_vec(),
+            column_names: HashMap::new(),
+        }
+    }
+}
+
+impl CsvDialect {
+    pub fn with_delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn with_quote(mut self, quote: u8) -> Self {
+        self.quote = quote;
+        self
+    }
+
+    pub fn without_quoting(mut self) -> Self {
+        self.quoting = false;
+        self
+    }
+
+    /// Files have no header row, their columns are `columns` in this order
+    pub fn without_headers(mut self, columns: Vec<String>) -> Self {
+        self.has_headers = false;
+        self.columns = columns;
+        self
+    }
+
+    /// Reads the feed's `from` column as the processor's `to` column
+    pub fn with_column_name(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.column_names.insert(from.into(), to.into());
+        self
+    }
+
+    /// Creates a CSV reader for `stream` that yields rows under the column names
+    /// the processor expects
+    pub async fn reader<R>(&self, stream: R) -> ProcessorResult<CsvStreamReader<InputStream>>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        // Headerless files get their header row from the dialect, so column names can
+        // be mapped the same way for every feed
+        let stream: InputStream = if self.has_headers {
+            Box::new(stream)
+        } else {
+            let header = format!("{}\n", self.columns.join(&char::from(self.delimiter).to_string()));
+            Box::new(Cursor::new(header.into_bytes()).chain(stream))
+        };
+
+        let mut reader = AsyncReaderBuilder::new()
+            .trim(Trim::All)
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .quoting(self.quoting)
+            .create_deserializer(stream);
+
+        if !self.column_names.is_empty() {
+            let headers: StringRecord = reader
+                .headers()
+                .await?
+                .iter()
+                .map(|name| self.column_names.get(name).map(String::as_str).unwrap_or(name))
+                .collect();
+            reader.set_headers(headers);
+        }
+
+        Ok(CsvStreamReader { reader })
+    }
+}
+
+/// Opens the file at `path` for reading, decompressing it on the fly if needed
+pub async fn open(path: &Path) -> ProcessorResult<InputStream> {
+    let file = tokio::fs::File::open(path).await?;
+    decompress(file, Some(path)).await
+}
+
+/// Returns the buffered bytes without consuming them
+async fn peek<R>(reader: &mut R) -> ProcessorResult<&[u8]>
+where
+    R: AsyncBufRead + Unpin,
+{
+    Ok(reader.fill_buf().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const CSV: &str = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+
+    async fn read_all(mut stream: InputStream) -> String {
+        let mut content = String::new();
+        stream.read_to_string(&mut content).await.unwrap();
+        content
+    }
+
+    #[tokio::test]
+    async fn detects_compression_by_magic_bytes() {
+        let mut gzip = GzipEncoder::new(Vec::new());
+        gzip.write_all(CSV.as_bytes()).await.unwrap();
+        gzip.shutdown().await.unwrap();
+
+        let mut zstd = ZstdEncoder::new(Vec::new());
+        zstd.write_all(CSV.as_bytes()).await.unwrap();
+        zstd.shutdown().await.unwrap();
+
+        for compressed in [gzip.into_inner(), zstd.into_inner(), CSV.as_bytes().to_vec()] {
+            let stream = decompress(std::io::Cursor::new(compressed), None).await.unwrap();
+            assert_eq!(read_all(stream).await, CSV);
+        }
+    }
+
+    #[tokio::test]
+    async fn dialect_maps_delimiter_and_column_names() {
+        let dialect = CsvDialect::default()
+            .with_delimiter(b';')
+            .with_column_name("kind", "type")
+            .with_column_name("client_id", "client")
+            .with_column_name("tx_id", "tx");
+
+        let csv = "kind;client_id;tx_id;amount\ndeposit;1;1;1.5\n";
+        let mut stream = dialect.reader(Cursor::new(csv.as_bytes().to_vec())).await.unwrap();
+        let headers = stream.reader.headers().await.unwrap().clone();
+        assert_eq!(headers, StringRecord::from(vec!["type", "client", "tx", "amount"]));
+
+        let dialect =
+            CsvDialect::default().without_headers(["client", "type", "tx", "amount"].map(String::from).to_vec());
+        let mut stream = dialect
+            .reader(Cursor::new(b"1,deposit,1,1.5\n".to_vec()))
+            .await
+            .unwrap();
+        let headers = stream.reader.headers().await.unwrap().clone();
+        assert_eq!(headers, StringRecord::from(vec!["client", "type", "tx", "amount"]));
+    }
+
+    #[test]
+    fn falls_back_to_extension_for_short_streams() {
+        assert_eq!(
+            Compression::detect(&[], Some(Path::new("tx.csv.gz"))),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::detect(&[], Some(Path::new("tx.csv.zst"))),
+            Compression::Zstd
+        );
+        assert_eq!(Compression::detect(&[], Some(Path::new("tx.csv"))), Compression::None);
+        assert_eq!(
+            Compression::detect(b"type,client", Some(Path::new("tx.gz"))),
+            Compression::None
+        );
+    }
+}
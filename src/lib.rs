@@ -1,12 +1,17 @@
-use std::str::FromStr;
+use std::{str::FromStr, time::Duration};
 
 use csv_async::{AsyncDeserializer, AsyncSerializer};
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::sync::{mpsc::error::TrySendError, oneshot::error::RecvError};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{
+    mpsc::error::{SendTimeoutError, TrySendError},
+    oneshot::error::RecvError,
+};
 
 pub mod channel_actor;
+pub mod input;
 pub mod wallet;
 
 #[derive(Error, Debug)]
@@ -14,12 +19,36 @@ pub enum ProcessorError {
     #[error("CSV parsing error: {0}")]
     CsvError(#[from] csv::Error),
 
+    #[error("CSV parsing error: {0}")]
+    CsvAsyncError(#[from] csv_async::Error),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
     #[error("Actor send error: {0}")]
     ActorTxSendError(String),
 
+    #[error("Actor mailbox full")]
+    ActorMailboxFull,
+
+    #[error("Actor mailbox still full after {timeout:?}")]
+    ActorTellTimeout { timeout: std::time::Duration },
+
+    #[error("Actor did not reply within {timeout:?}")]
+    ActorAskTimeout { timeout: std::time::Duration },
+
+    #[error("An actor is already registered as {name}")]
+    ActorAlreadyRegistered { name: String },
+
+    #[error("No reply from {actors}")]
+    MissingReplies { actors: String },
+
     #[error("Actor recv error: {0}")]
     ActorRecvError(String),
 
+    #[error("Actor task failed: {0}")]
+    ActorJoinError(String),
+
     #[error("Invalid amount: {message}")]
     InvalidAmount { message: String },
 
@@ -27,19 +56,47 @@ pub enum ProcessorError {
     InvalidTransaction { message: String },
 
     #[error("Account locked: client {client}")]
-    AccountLocked { client: u16 },
+    AccountLocked { client: ClientId },
 
-    #[error("Insufficient funds: available {available}, required {required}")]
+    #[error("Insufficient funds: available {available}, required {required}, overdraft limit {limit}")]
     InsufficientFunds {
         available: rust_decimal::Decimal,
         required: rust_decimal::Decimal,
+        limit: rust_decimal::Decimal,
+    },
+
+    #[error("Limit exceeded for client {client}: {rule}")]
+    LimitExceeded {
+        client: ClientId,
+        rule: wallet::limits::LimitRule,
+    },
+
+    #[error("Arithmetic overflow applying tx {tx_id}")]
+    Overflow { tx_id: TxId },
+
+    #[error("Arithmetic overflow adding up the balance of client {client}")]
+    BalanceOverflow { client: ClientId },
+
+    #[error("Timestamp {timestamp} of tx {tx_id} is before the last one seen for client {client} ({last})")]
+    NonMonotonicTimestamp {
+        client: ClientId,
+        tx_id: TxId,
+        timestamp: u64,
+        last: u64,
     },
 
     #[error("Transaction not found: {tx_id}")]
-    TransactionNotFound { tx_id: u32 },
+    TransactionNotFound { tx_id: TxId },
 
     #[error("Duplicate transaction: {tx_id}")]
-    DuplicateTransaction { tx_id: u32 },
+    DuplicateTransaction { tx_id: TxId },
+
+    #[error("Amount {requested} exceeds remaining {remaining} for tx {tx_id}")]
+    AmountExceedsRemaining {
+        tx_id: TxId,
+        remaining: rust_decimal::Decimal,
+        requested: rust_decimal::Decimal,
+    },
 
     #[error("Invalid transaction state for dispute")]
     InvalidDisputeState,
@@ -49,11 +106,23 @@ pub enum ProcessorError {
 
     #[error("Serialization error: {0}")]
     Serialization(String),
+
+    #[error("Errors are only reported when the error report is enabled in the config")]
+    ErrorReportDisabled,
 }
 
 pub fn map_channel_send_err<M>(err: TrySendError<M>) -> ProcessorError {
-    let e = format!("{}", err);
-    ProcessorError::ActorTxSendError(e)
+    match err {
+        TrySendError::Full(_) => ProcessorError::ActorMailboxFull,
+        TrySendError::Closed(_) => ProcessorError::ActorTxSendError(format!("{}", err)),
+    }
+}
+
+pub fn map_channel_send_timeout_err<M>(err: SendTimeoutError<M>, timeout: Duration) -> ProcessorError {
+    match err {
+        SendTimeoutError::Timeout(_) => ProcessorError::ActorTellTimeout { timeout },
+        SendTimeoutError::Closed(_) => ProcessorError::ActorTxSendError(format!("{}", err)),
+    }
 }
 
 pub fn map_channel_recv_err(err: RecvError) -> ProcessorError {
@@ -63,9 +132,15 @@ pub fn map_channel_recv_err(err: RecvError) -> ProcessorError {
 
 pub type ProcessorResult<T> = std::result::Result<T, ProcessorError>;
 
+/// Identifies a client across the input, wallets and output
+pub type ClientId = u64;
+
+/// Identifies a transaction within a client's history
+pub type TxId = u64;
+
 unsafe impl Send for ProcessorError {}
 
-#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -73,19 +148,56 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    #[serde(alias = "reversal")]
+    Refund,
+    // Admin transaction setting the client's overdraft limit to `amount`
+    Overdraft,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Transaction {
     #[serde(rename = "type")]
     pub tx_type: TransactionType,
-    pub client: u16,
+    pub client: ClientId,
     #[serde(rename = "tx")]
-    pub id: u32,
+    pub id: TxId,
     #[serde(deserialize_with = "deserialize_opt_amount")]
     pub amount: Option<Decimal>,
+    // Event time in seconds since epoch, from the optional `timestamp` column
+    #[serde(default)]
+    pub timestamp: Option<u64>,
     #[serde(default = "default_disputed")]
     pub disputed: bool,
+    // Portion of the amount currently held under dispute
+    #[serde(skip)]
+    pub held: Decimal,
+    // Portion of the amount already reversed by chargebacks
+    #[serde(skip)]
+    pub charged_back: Decimal,
+    // Portion of the amount already reversed by refunds
+    #[serde(skip)]
+    pub refunded: Decimal,
+    // Fee charged for the transaction: taken out of a deposit, on top of a withdrawal
+    #[serde(skip)]
+    pub fee: Decimal,
+    // Processing time used for the velocity limits when there is no timestamp,
+    // recorded by supervised WalletActors so a replay decides the same way
+    #[serde(skip)]
+    pub processed_at: Option<u64>,
+}
+
+impl Transaction {
+    /// Amount that is neither held under dispute nor already reversed, i.e. what can
+    /// still be disputed or refunded. A deposit only counts what was credited after
+    /// its fee, the fee itself is kept by the house account.
+    pub fn remaining(&self) -> Decimal {
+        let amount = self.amount.unwrap_or_default();
+        let credited = match self.tx_type {
+            TransactionType::Deposit => amount - self.fee,
+            _ => amount,
+        };
+        credited - self.held - self.charged_back - self.refunded
+    }
 }
 
 fn deserialize_opt_amount<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
@@ -104,11 +216,17 @@ fn default_disputed() -> bool {
 }
 
 /// A streaming CSV reader
-pub struct CsvStreamReader<'a> {
-    pub reader: AsyncDeserializer<&'a mut tokio::fs::File>,
+pub struct CsvStreamReader<R>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    pub reader: AsyncDeserializer<R>,
 }
 
 /// A streaming CSV writer
-pub struct CsvStreamWriter {
-    pub writer: AsyncSerializer<tokio::io::Stdout>,
+pub struct CsvStreamWriter<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    pub writer: AsyncSerializer<W>,
 }
@@ -0,0 +1,156 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use futures::StreamExt;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use tokio::io::AsyncRead;
+
+use crate::{ClientId, CsvStreamReader, ProcessorError, ProcessorResult, channel_actor::Supervision};
+
+use super::{
+    fees::FeeSchedule,
+    fraud::FraudConfig,
+    limits::VelocityConfig,
+    precision::Precision,
+    rules::{RuleSet, TransactionRule},
+};
+
+/// Settings shared by every wallet of a `TransactionProcessor`
+#[derive(Clone, Debug, Default)]
+pub struct WalletConfig {
+    pub fees: FeeSchedule,
+    // Per-client overdraft limits; clients not listed have none
+    pub overdraft_limits: HashMap<ClientId, Decimal>,
+    // Highest limit `overdraft` rows in the transaction feed may set. Those rows are
+    // rejected when unset.
+    pub admin_overdraft_max: Option<Decimal>,
+    pub velocity: VelocityConfig,
+    pub rules: RuleSet,
+    pub fraud: FraudConfig,
+    pub precision: Precision,
+    // Reject transactions whose timestamp is before the client's previous one
+    pub monotonic_timestamps: bool,
+    // How many disputes, resolves, chargebacks and refunds referencing a not yet seen
+    // transaction are parked per client. 0 disables the reorder buffer.
+    pub reorder_buffer_size: usize,
+    // How WalletActors are restarted when they fail. Unset runs them unsupervised, as
+    // every snapshot clones all of an actor's wallets.
+    pub supervision: Option<Supervision>,
+    // Transactions sent to a WalletActor at once; 0 and 1 send them one by one
+    pub batch_size: usize,
+    // Longest a transaction waits for its batch to fill up, while the input is idle
+    pub batch_flush_interval: Option<Duration>,
+    // Keep the transactions WalletActors could not apply for the error report
+    pub error_report: bool,
+    // Longest the output and reports wait for each WalletActor to reply, waiting for
+    // the transactions queued before the request included. Unset waits for as long as
+    // it takes.
+    pub reply_timeout: Option<Duration>,
+}
+
+#[derive(Deserialize)]
+struct OverdraftLimitRecord {
+    client: ClientId,
+    limit: Decimal,
+}
+
+impl WalletConfig {
+    pub fn with_fees(mut self, fees: FeeSchedule) -> Self {
+        self.fees = fees;
+        self
+    }
+
+    pub fn with_velocity(mut self, velocity: VelocityConfig) -> Self {
+        self.velocity = velocity;
+        self
+    }
+
+    pub fn with_fraud(mut self, fraud: FraudConfig) -> Self {
+        self.fraud = fraud;
+        self
+    }
+
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Bounds for deposit and withdrawal amounts, both inclusive
+    pub fn with_amount_bounds(mut self, min: Option<Decimal>, max: Option<Decimal>) -> Self {
+        self.rules.amount.min = min;
+        self.rules.amount.max = max;
+        self
+    }
+
+    pub fn with_monotonic_timestamps(mut self, monotonic_timestamps: bool) -> Self {
+        self.monotonic_timestamps = monotonic_timestamps;
+        self
+    }
+
+    pub fn with_reorder_buffer(mut self, size: usize) -> Self {
+        self.reorder_buffer_size = size;
+        self
+    }
+
+    pub fn with_supervision(mut self, supervision: Supervision) -> Self {
+        self.supervision = Some(supervision);
+        self
+    }
+
+    pub fn with_batching(mut self, size: usize, flush_interval: Duration) -> Self {
+        self.batch_size = size;
+        self.batch_flush_interval = Some(flush_interval);
+        self
+    }
+
+    pub fn with_reply_timeout(mut self, timeout: Duration) -> Self {
+        self.reply_timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_error_report(mut self, error_report: bool) -> Self {
+        self.error_report = error_report;
+        self
+    }
+
+    /// Registers a validation rule, run after the ones already registered
+    pub fn with_rule(mut self, rule: Arc<dyn TransactionRule>) -> Self {
+        self.rules.register(rule);
+        self
+    }
+
+    /// Accepts `overdraft` rows from the transaction feed, setting limits up to `max`
+    pub fn with_admin_overdrafts(mut self, max: Decimal) -> Self {
+        self.admin_overdraft_max = Some(max);
+        self
+    }
+
+    pub fn with_overdraft_limit(mut self, client: ClientId, limit: Decimal) -> Self {
+        self.overdraft_limits.insert(client, limit);
+        self
+    }
+
+    /// Reads overdraft limits from a `client,limit` CSV
+    pub async fn load_overdraft_limits<R>(mut self, mut stream: CsvStreamReader<R>) -> ProcessorResult<Self>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        let mut records = stream.reader.deserialize::<OverdraftLimitRecord>();
+        while let Some(result) = records.next().await {
+            let record = result?;
+            if record.limit < Decimal::ZERO {
+                return Err(ProcessorError::InvalidAmount {
+                    message: format!("negative overdraft limit for client={}", record.client),
+                });
+            }
+
+            self.overdraft_limits.insert(record.client, record.limit);
+        }
+
+        Ok(self)
+    }
+
+    pub fn overdraft_limit(&self, client: ClientId) -> Decimal {
+        self.overdraft_limits.get(&client).copied().unwrap_or_default()
+    }
+}
@@ -0,0 +1,169 @@
+use rust_decimal::Decimal;
+
+use crate::{ClientId, ProcessorError, ProcessorResult, Transaction, TransactionType};
+
+/// How a fee is derived from a transaction amount
+#[derive(Clone, Debug)]
+pub enum FeeKind {
+    /// A fixed fee regardless of the amount
+    Flat(Decimal),
+    /// A percentage of the amount, e.g. `1.5` for 1.5%
+    Percentage(Decimal),
+    /// Picks the tier with the highest `from` that the amount reaches
+    Tiered(Vec<FeeTier>),
+}
+
+#[derive(Clone, Debug)]
+pub struct FeeTier {
+    pub from: Decimal,
+    pub kind: FeeKind,
+}
+
+/// A fee for one transaction type, optionally clamped to `[min, max]`
+#[derive(Clone, Debug)]
+pub struct FeeRule {
+    pub kind: FeeKind,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+}
+
+impl FeeRule {
+    pub fn new(kind: FeeKind) -> Self {
+        Self {
+            kind,
+            min: None,
+            max: None,
+        }
+    }
+
+    pub fn with_min(mut self, min: Decimal) -> Self {
+        self.min = Some(min);
+        self
+    }
+
+    pub fn with_max(mut self, max: Decimal) -> Self {
+        self.max = Some(max);
+        self
+    }
+
+    /// The fee for `amount`, `None` when it is too large to represent
+    pub fn fee_for(&self, amount: Decimal) -> Option<Decimal> {
+        let mut fee = self.kind.fee_for(amount)?;
+        if let Some(min) = self.min {
+            fee = fee.max(min);
+        }
+        if let Some(max) = self.max {
+            fee = fee.min(max);
+        }
+
+        Some(fee.max(Decimal::ZERO))
+    }
+}
+
+impl FeeKind {
+    fn fee_for(&self, amount: Decimal) -> Option<Decimal> {
+        match self {
+            FeeKind::Flat(fee) => Some(*fee),
+            FeeKind::Percentage(percent) => amount.checked_mul(*percent)?.checked_div(Decimal::ONE_HUNDRED),
+            FeeKind::Tiered(tiers) => tiers
+                .iter()
+                .filter(|tier| tier.from <= amount)
+                .max_by_key(|tier| tier.from)
+                .map_or(Some(Decimal::ZERO), |tier| tier.kind.fee_for(amount)),
+        }
+    }
+}
+
+/// Fees charged per transaction type. Every fee is credited to `house_account` as
+/// soon as it is charged.
+#[derive(Clone, Debug, Default)]
+pub struct FeeSchedule {
+    pub house_account: ClientId,
+    pub deposit: Option<FeeRule>,
+    pub withdrawal: Option<FeeRule>,
+}
+
+impl FeeSchedule {
+    pub fn new(house_account: ClientId) -> Self {
+        Self {
+            house_account,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_deposit_fee(mut self, rule: FeeRule) -> Self {
+        self.deposit = Some(rule);
+        self
+    }
+
+    pub fn with_withdrawal_fee(mut self, rule: FeeRule) -> Self {
+        self.withdrawal = Some(rule);
+        self
+    }
+
+    /// Fee owed for a transaction. Only deposits and withdrawals are charged. The fee
+    /// is not rounded, that's up to the configured `Precision`.
+    pub fn fee_for(&self, tx: &Transaction) -> ProcessorResult<Decimal> {
+        let rule = match tx.tx_type {
+            TransactionType::Deposit => self.deposit.as_ref(),
+            TransactionType::Withdrawal => self.withdrawal.as_ref(),
+            _ => None,
+        };
+
+        match (rule, tx.amount) {
+            (Some(rule), Some(amount)) => rule.fee_for(amount).ok_or(ProcessorError::Overflow { tx_id: tx.id }),
+            _ => Ok(Decimal::ZERO),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::prelude::FromPrimitive;
+
+    fn dec(v: f64) -> Decimal {
+        Decimal::from_f64(v).unwrap()
+    }
+
+    #[test]
+    fn flat_and_percentage_fees() {
+        assert_eq!(
+            FeeRule::new(FeeKind::Flat(dec(0.5))).fee_for(dec(100.0)),
+            Some(dec(0.5))
+        );
+        assert_eq!(
+            FeeRule::new(FeeKind::Percentage(dec(1.5))).fee_for(dec(200.0)),
+            Some(dec(3.0))
+        );
+        assert_eq!(FeeRule::new(FeeKind::Percentage(dec(1.5))).fee_for(Decimal::MAX), None);
+    }
+
+    #[test]
+    fn fee_is_clamped_to_min_and_max() {
+        let rule = FeeRule::new(FeeKind::Percentage(dec(1.0)))
+            .with_min(dec(1.0))
+            .with_max(dec(5.0));
+
+        assert_eq!(rule.fee_for(dec(10.0)), Some(dec(1.0)));
+        assert_eq!(rule.fee_for(dec(300.0)), Some(dec(3.0)));
+        assert_eq!(rule.fee_for(dec(1000.0)), Some(dec(5.0)));
+    }
+
+    #[test]
+    fn tiered_fee_picks_highest_reached_tier() {
+        let rule = FeeRule::new(FeeKind::Tiered(vec![
+            FeeTier {
+                from: Decimal::ZERO,
+                kind: FeeKind::Flat(dec(1.0)),
+            },
+            FeeTier {
+                from: dec(1000.0),
+                kind: FeeKind::Percentage(dec(0.5)),
+            },
+        ]));
+
+        assert_eq!(rule.fee_for(dec(999.0)), Some(dec(1.0)));
+        assert_eq!(rule.fee_for(dec(2000.0)), Some(dec(10.0)));
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeSet;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{TransactionType, TxId};
+
+use super::wallet_actor::Wallet;
+
+/// A suspicious pattern detected on a client's account
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FraudFlag {
+    HighDisputeRatio,
+    RepeatedChargebacks,
+    RapidDepositWithdrawCycles,
+    LargeWithdrawalAfterDeposit,
+}
+
+/// Thresholds for the built-in fraud heuristics. Unset thresholds are not checked.
+/// "Right after" means the previous transaction of the same client.
+#[derive(Clone, Debug, Default)]
+pub struct FraudConfig {
+    // Flag when disputes opened on deposits / all deposits goes above this ratio
+    pub max_dispute_ratio: Option<Decimal>,
+    // Flag when more chargebacks than this have been applied, partial ones included
+    pub max_chargebacks: Option<usize>,
+    // Flag when more withdrawals than this came right after a deposit
+    pub max_rapid_cycles: Option<usize>,
+    // Flag a withdrawal right after a deposit of at least this share of the deposit
+    pub large_withdrawal_ratio: Option<Decimal>,
+    // Lock flagged accounts
+    pub auto_freeze: bool,
+}
+
+/// What a wallet needs to remember for the heuristics besides its history
+#[derive(Clone, Debug, Default)]
+pub struct FraudState {
+    pub flags: BTreeSet<FraudFlag>,
+    // Amount of the previous transaction when it was a deposit
+    last_deposit: Option<Decimal>,
+    rapid_cycles: usize,
+    deposits: u64,
+    // Disputes opened on deposits, resolved ones included
+    deposit_disputes: u64,
+    // Chargebacks applied, partial ones included
+    chargebacks: usize,
+}
+
+impl FraudConfig {
+    /// Thresholds that flag the usual suspects without being too noisy
+    pub fn recommended() -> Self {
+        Self {
+            max_dispute_ratio: Some(Decimal::new(5, 1)),
+            max_chargebacks: Some(1),
+            max_rapid_cycles: Some(3),
+            large_withdrawal_ratio: Some(Decimal::new(9, 1)),
+            auto_freeze: false,
+        }
+    }
+
+    /// Updates the wallet's flags after `tx_type`, referencing `tx_id`, has been applied to it
+    pub fn assess(&self, wallet: &mut Wallet, tx_type: &TransactionType, tx_id: TxId, amount: Option<Decimal>) {
+        let mut flags = Vec::new();
+        let last_deposit = wallet.fraud.last_deposit.take();
+
+        match tx_type {
+            TransactionType::Deposit => {
+                wallet.fraud.last_deposit = amount;
+                wallet.fraud.deposits += 1;
+            }
+            TransactionType::Withdrawal => {
+                if let Some(deposit) = last_deposit {
+                    wallet.fraud.rapid_cycles += 1;
+                    if self.max_rapid_cycles.is_some_and(|max| wallet.fraud.rapid_cycles > max) {
+                        flags.push(FraudFlag::RapidDepositWithdrawCycles);
+                    }
+
+                    let withdrawn = amount.unwrap_or_default();
+                    // A threshold too large to represent is never reached
+                    if self
+                        .large_withdrawal_ratio
+                        .and_then(|ratio| deposit.checked_mul(ratio))
+                        .is_some_and(|threshold| withdrawn >= threshold)
+                    {
+                        flags.push(FraudFlag::LargeWithdrawalAfterDeposit);
+                    }
+                }
+            }
+            TransactionType::Dispute => {
+                let on_deposit = wallet
+                    .transactions
+                    .get(&tx_id)
+                    .is_some_and(|tx| tx.tx_type == TransactionType::Deposit);
+                if on_deposit {
+                    wallet.fraud.deposit_disputes += 1;
+                }
+
+                let (deposits, disputes) = (wallet.fraud.deposits, wallet.fraud.deposit_disputes);
+                if let Some(max_ratio) = self.max_dispute_ratio
+                    && deposits > 0
+                    && Decimal::from(disputes) / Decimal::from(deposits) > max_ratio
+                {
+                    flags.push(FraudFlag::HighDisputeRatio);
+                }
+            }
+            TransactionType::Chargeback => {
+                wallet.fraud.chargebacks += 1;
+                if self.max_chargebacks.is_some_and(|max| wallet.fraud.chargebacks > max) {
+                    flags.push(FraudFlag::RepeatedChargebacks);
+                }
+            }
+            _ => {}
+        }
+
+        if !flags.is_empty() && self.auto_freeze {
+            wallet.locked = true;
+        }
+        wallet.fraud.flags.extend(flags);
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rust_decimal::Decimal;
+
+use crate::{ClientId, ProcessorError, ProcessorResult, Transaction, TransactionType};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The velocity rule that rejected a transaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitRule {
+    MaxSingleWithdrawal,
+    MaxDailyWithdrawal,
+    MaxTransactionsPerWindow,
+}
+
+impl fmt::Display for LimitRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LimitRule::MaxSingleWithdrawal => "max single withdrawal",
+            LimitRule::MaxDailyWithdrawal => "max daily withdrawal",
+            LimitRule::MaxTransactionsPerWindow => "max transactions per window",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// At most `count` deposits and withdrawals within any `window`
+#[derive(Clone, Copy, Debug)]
+pub struct TransactionWindow {
+    pub count: usize,
+    pub window: Duration,
+}
+
+/// Rate and amount controls for a client. Unset limits are not enforced.
+#[derive(Clone, Debug, Default)]
+pub struct VelocityLimits {
+    pub max_single_withdrawal: Option<Decimal>,
+    pub max_daily_withdrawal: Option<Decimal>,
+    pub max_transactions: Option<TransactionWindow>,
+}
+
+/// Velocity limits resolved per client: a client override wins over the client's
+/// tier, which wins over the default limits.
+#[derive(Clone, Debug, Default)]
+pub struct VelocityConfig {
+    pub default: VelocityLimits,
+    pub tiers: HashMap<String, VelocityLimits>,
+    pub client_tiers: HashMap<ClientId, String>,
+    pub clients: HashMap<ClientId, VelocityLimits>,
+}
+
+impl VelocityConfig {
+    pub fn limits_for(&self, client: ClientId) -> &VelocityLimits {
+        if let Some(limits) = self.clients.get(&client) {
+            return limits;
+        }
+
+        self.client_tiers
+            .get(&client)
+            .and_then(|tier| self.tiers.get(tier))
+            .unwrap_or(&self.default)
+    }
+}
+
+/// What a wallet needs to remember to enforce its velocity limits
+#[derive(Clone, Debug, Default)]
+pub struct VelocityState {
+    day: u64,
+    withdrawn_today: Decimal,
+    // Seconds at which recent deposits and withdrawals were applied
+    recent: VecDeque<u64>,
+}
+
+impl VelocityState {
+    /// Fails with the first limit `tx` would break at time `now` (seconds since epoch)
+    pub fn check(&self, limits: &VelocityLimits, tx: &Transaction, now: u64) -> ProcessorResult<()> {
+        if !matches!(tx.tx_type, TransactionType::Deposit | TransactionType::Withdrawal) {
+            return Ok(());
+        }
+
+        let exceeded = |rule| ProcessorError::LimitExceeded {
+            client: tx.client,
+            rule,
+        };
+
+        if let Some(window) = limits.max_transactions {
+            let since = now.saturating_sub(window.window.as_secs());
+            let in_window = self.recent.iter().filter(|&&at| at > since).count();
+            if in_window >= window.count {
+                return Err(exceeded(LimitRule::MaxTransactionsPerWindow));
+            }
+        }
+
+        if tx.tx_type == TransactionType::Withdrawal {
+            let amount = tx.amount.unwrap_or_default();
+            if limits.max_single_withdrawal.is_some_and(|max| amount > max) {
+                return Err(exceeded(LimitRule::MaxSingleWithdrawal));
+            }
+
+            let withdrawn_today = if self.day == now / SECONDS_PER_DAY {
+                self.withdrawn_today
+            } else {
+                Decimal::ZERO
+            };
+            if let Some(max) = limits.max_daily_withdrawal {
+                let withdrawn = withdrawn_today
+                    .checked_add(amount)
+                    .ok_or(ProcessorError::Overflow { tx_id: tx.id })?;
+                if withdrawn > max {
+                    return Err(exceeded(LimitRule::MaxDailyWithdrawal));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Accounts for an applied transaction
+    pub fn record(&mut self, limits: &VelocityLimits, tx_type: &TransactionType, amount: Option<Decimal>, now: u64) {
+        if !matches!(tx_type, TransactionType::Deposit | TransactionType::Withdrawal) {
+            return;
+        }
+
+        if let Some(window) = limits.max_transactions {
+            let since = now.saturating_sub(window.window.as_secs());
+            while self.recent.front().is_some_and(|&at| at <= since) {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(now);
+        }
+
+        if *tx_type == TransactionType::Withdrawal && limits.max_daily_withdrawal.is_some() {
+            let day = now / SECONDS_PER_DAY;
+            if self.day != day {
+                self.day = day;
+                self.withdrawn_today = Decimal::ZERO;
+            }
+            // `check` made sure the sum fits, it is at most the daily limit
+            self.withdrawn_today += amount.unwrap_or_default();
+        }
+    }
+}
+
+/// Current processing time in seconds since epoch
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_override_wins_over_tier_and_default() {
+        let tier_limits = VelocityLimits {
+            max_single_withdrawal: Some(Decimal::TEN),
+            ..Default::default()
+        };
+        let client_limits = VelocityLimits {
+            max_single_withdrawal: Some(Decimal::ONE),
+            ..Default::default()
+        };
+
+        let mut config = VelocityConfig::default();
+        config.tiers.insert("gold".to_string(), tier_limits);
+        config.client_tiers.insert(1, "gold".to_string());
+        config.client_tiers.insert(2, "gold".to_string());
+        config.clients.insert(2, client_limits);
+
+        assert_eq!(config.limits_for(1).max_single_withdrawal, Some(Decimal::TEN));
+        assert_eq!(config.limits_for(2).max_single_withdrawal, Some(Decimal::ONE));
+        assert_eq!(config.limits_for(3).max_single_withdrawal, None);
+    }
+}
@@ -1,2 +1,8 @@
+pub mod config;
+pub mod fees;
+pub mod fraud;
+pub mod limits;
+pub mod precision;
 pub mod processor;
+pub mod rules;
 pub mod wallet_actor;
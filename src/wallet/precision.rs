@@ -0,0 +1,128 @@
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::{ProcessorError, ProcessorResult, Transaction};
+
+/// How amounts are brought down to the configured scale
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round half to even
+    #[default]
+    Bankers,
+    /// Round half away from zero
+    HalfUp,
+    /// Drop the extra digits
+    Truncate,
+}
+
+impl From<Rounding> for RoundingStrategy {
+    fn from(rounding: Rounding) -> Self {
+        match rounding {
+            Rounding::Bankers => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Truncate => RoundingStrategy::ToZero,
+        }
+    }
+}
+
+/// Number of decimal places amounts and balances are kept at. The same rounding is
+/// applied to input amounts, computed fees and the output, so internal balances
+/// never carry more precision than what is reported.
+#[derive(Clone, Copy, Debug)]
+pub struct Precision {
+    pub scale: u32,
+    pub rounding: Rounding,
+    // Reject input amounts with more decimal places than `scale` instead of rounding them
+    pub strict: bool,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Self {
+            scale: 4,
+            rounding: Rounding::default(),
+            strict: false,
+        }
+    }
+}
+
+impl Precision {
+    pub fn round(&self, value: Decimal) -> Decimal {
+        value.round_dp_with_strategy(self.scale, self.rounding.into())
+    }
+
+    /// Validates the scale of the transaction amount and rounds it to `scale`
+    pub fn apply(&self, tx: &mut Transaction) -> ProcessorResult<()> {
+        let Some(amount) = tx.amount else {
+            return Ok(());
+        };
+
+        if self.strict && amount.normalize().scale() > self.scale {
+            return Err(ProcessorError::InvalidAmount {
+                message: format!(
+                    "amount {} exceeds {} decimal places for tx_id={}",
+                    amount, self.scale, tx.id
+                ),
+            });
+        }
+
+        tx.amount = Some(self.round(amount));
+        Ok(())
+    }
+
+    /// Formats a balance for output, always showing `scale` decimal places
+    pub fn format(&self, value: Decimal) -> String {
+        format!("{:.*}", self.scale as usize, self.round(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn dec(s: &str) -> Decimal {
+        Decimal::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn rounding_strategies() {
+        let precision = |rounding| Precision {
+            scale: 2,
+            rounding,
+            strict: false,
+        };
+
+        assert_eq!(precision(Rounding::Bankers).round(dec("1.125")), dec("1.12"));
+        assert_eq!(precision(Rounding::HalfUp).round(dec("1.125")), dec("1.13"));
+        assert_eq!(precision(Rounding::Truncate).round(dec("1.129")), dec("1.12"));
+        assert_eq!(precision(Rounding::Bankers).format(dec("3")), "3.00");
+    }
+
+    #[test]
+    fn strict_precision_rejects_extra_decimal_places() {
+        let precision = Precision {
+            strict: true,
+            ..Default::default()
+        };
+        let mut tx = Transaction {
+            tx_type: crate::TransactionType::Deposit,
+            client: 1,
+            id: 1,
+            amount: Some(dec("1.00001")),
+            timestamp: None,
+            disputed: false,
+            held: Decimal::ZERO,
+            charged_back: Decimal::ZERO,
+            refunded: Decimal::ZERO,
+            fee: Decimal::ZERO,
+            processed_at: None,
+        };
+
+        assert!(precision.apply(&mut tx).is_err());
+
+        // Trailing zeros do not count as precision
+        tx.amount = Some(dec("1.500000"));
+        precision.apply(&mut tx).unwrap();
+        assert_eq!(tx.amount, Some(dec("1.5")));
+    }
+}
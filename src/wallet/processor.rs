@@ -1,36 +1,104 @@
-use futures::StreamExt;
+use std::sync::Arc;
+
+use futures::{FutureExt, StreamExt};
 use rust_decimal::Decimal;
 use serde::Serialize;
-use tokio::sync::oneshot;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+    time::Instant,
+};
 
 use crate::{
-    CsvStreamReader, CsvStreamWriter, ProcessorError, ProcessorResult, Transaction, TransactionType,
-    channel_actor::{self, ActorRef},
+    ClientId, CsvStreamReader, CsvStreamWriter, ProcessorError, ProcessorResult, Transaction, TransactionType, TxId,
+    channel_actor::{
+        self, ActorError, ActorFailure, ActorHandle, ActorOptions, ActorRef, ActorRegistry, DeadLetterReason,
+        DeadLetters, ErrorSink,
+    },
 };
 
-use super::wallet_actor::{WalletActor, WalletActorMessages, WalletState};
+use super::{
+    config::WalletConfig,
+    fraud::FraudFlag,
+    precision::Precision,
+    wallet_actor::{ExpiryReason, WalletActor, WalletActorMessages, WalletState},
+};
 
 pub struct TransactionProcessor {
     actor_count: usize,
-    wallet_actors: Vec<ActorRef<WalletActorMessages>>,
+    wallet_actors: Vec<ActorHandle<WalletActor, WalletActorMessages>>,
+    config: Arc<WalletConfig>,
+    failures: mpsc::UnboundedReceiver<ActorFailure>,
+    // Only set up when the config asks for the error report
+    errors: Option<mpsc::UnboundedReceiver<ActorError<WalletActorMessages>>>,
+    // Reports the rows rejected before reaching a WalletActor next to the actors' errors
+    error_sink: Option<ErrorSink<WalletActorMessages>>,
+    dead_letters: DeadLetters<WalletActorMessages>,
+    // Every actor of the processor by name, the WalletActors are `wallet-<index>`
+    registry: ActorRegistry,
+    // Transactions waiting to be sent, per WalletActor
+    batches: Vec<Vec<Transaction>>,
+    // When the pending batches are sent even if they are not full
+    flush_deadline: Option<Instant>,
 }
 
 #[derive(Serialize)]
 struct WalletCsvView {
-    client: u16,
+    client: ClientId,
     available: String,
     held: String,
     total: String,
     locked: bool,
 }
 
-impl From<WalletState> for WalletCsvView {
-    fn from(state: WalletState) -> Self {
+#[derive(Serialize)]
+struct FraudCsvView {
+    client: ClientId,
+    flag: FraudFlag,
+}
+
+#[derive(Serialize)]
+struct ExpiredCsvView {
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    client: ClientId,
+    tx: TxId,
+    amount: Option<Decimal>,
+    timestamp: Option<u64>,
+    reason: ExpiryReason,
+}
+
+#[derive(Serialize)]
+struct DeadLetterCsvView {
+    actor: String,
+    reason: DeadLetterReason,
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    client: ClientId,
+    tx: TxId,
+    amount: Option<Decimal>,
+    timestamp: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ErrorCsvView {
+    actor: String,
+    error: String,
+    #[serde(rename = "type")]
+    tx_type: Option<TransactionType>,
+    client: Option<ClientId>,
+    tx: Option<TxId>,
+    amount: Option<Decimal>,
+    timestamp: Option<u64>,
+}
+
+impl WalletCsvView {
+    fn new(state: WalletState, precision: &Precision) -> Self {
         Self {
             client: state.client,
-            available: format!("{:.4}", state.wallet.available.round_dp(4)),
-            held: format!("{:.4}", state.wallet.held.round_dp(4)),
-            total: format!("{:.4}", state.wallet.total.round_dp(4)),
+            available: precision.format(state.wallet.available),
+            held: precision.format(state.wallet.held),
+            total: precision.format(state.wallet.total),
             locked: state.wallet.locked,
         }
     }
@@ -39,75 +107,340 @@ impl From<WalletState> for WalletCsvView {
 impl TransactionProcessor {
     /// Creates actors with bounded channels
     pub async fn new(actor_count: usize, channel_buffer_size: usize) -> Self {
+        Self::with_config(actor_count, channel_buffer_size, WalletConfig::default()).await
+    }
+
+    /// Creates actors with bounded channels, sharing `config` across all wallets
+    pub async fn with_config(actor_count: usize, channel_buffer_size: usize, config: WalletConfig) -> Self {
+        let config = Arc::new(config);
+        let (failures_tx, failures) = mpsc::unbounded_channel();
+        let (errors_tx, errors) = match config.error_report {
+            true => {
+                let (errors_tx, errors) = mpsc::unbounded_channel();
+                (Some(errors_tx), Some(errors))
+            }
+            false => (None, None),
+        };
+        // Only transactions can be replayed, dropping the reports closes their reply senders
+        let dead_letters = DeadLetters::keeping(|msg| matches!(msg, WalletActorMessages::Tx(_)));
+        let registry = ActorRegistry::default();
         let mut wallet_actors = Vec::with_capacity(actor_count);
-        for _ in 0..actor_count {
-            let actor = WalletActor::create();
-            let actor_ref = channel_actor::start(actor, channel_buffer_size).await;
-            wallet_actors.push(actor_ref);
+        for index in 0..actor_count {
+            let actor = WalletActor::create(config.clone());
+            let mut options = ActorOptions::new(format!("wallet-{}", index)).with_dead_letters(dead_letters.clone());
+            if let Some(errors_tx) = &errors_tx {
+                options = options.with_errors(errors_tx.clone());
+            }
+            let actor_handle = match &config.supervision {
+                Some(supervision) => {
+                    channel_actor::start_supervised(
+                        actor,
+                        channel_buffer_size,
+                        options,
+                        supervision.clone(),
+                        failures_tx.clone(),
+                    )
+                    .await
+                }
+                None => channel_actor::start_with(actor, channel_buffer_size, options).await,
+            };
+            registry
+                .register(actor_handle.actor_ref().clone())
+                .expect("WalletActor names are unique");
+            wallet_actors.push(actor_handle);
         }
 
         Self {
             actor_count,
             wallet_actors,
+            config,
+            failures,
+            errors,
+            error_sink: errors_tx,
+            dead_letters,
+            registry,
+            batches: vec![Vec::new(); actor_count],
+            flush_deadline: None,
         }
     }
 
-    pub async fn process(&mut self, mut stream: CsvStreamReader<'_>) -> ProcessorResult<()> {
+    /// The registry the WalletActors are registered in, other actors working
+    /// alongside them can be registered here too
+    pub fn registry(&self) -> &ActorRegistry {
+        &self.registry
+    }
+
+    fn actor_refs(&self) -> impl Iterator<Item = &ActorRef<WalletActorMessages>> {
+        self.wallet_actors.iter().map(ActorHandle::actor_ref)
+    }
+
+    pub async fn process<R>(&mut self, mut stream: CsvStreamReader<R>) -> ProcessorResult<()>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
         let mut records = stream.reader.deserialize::<Transaction>();
-        while let Some(result) = records.next().await {
-            let tx = match result {
-                Ok(transaction) => transaction,
-                Err(e) => {
-                    eprintln!("Error deserializing record: {}", e);
-                    continue;
-                }
+        while let Some(tx) = self.next_transaction(&mut records).await? {
+            self.dispatch(tx).await?;
+        }
+
+        self.flush().await
+    }
+
+    /// Processes several inputs as one, interleaving their rows by the timestamp
+    /// column. Rows without a timestamp go first, and ties keep the order of the
+    /// inputs, so each input must itself be sorted by timestamp.
+    pub async fn process_merged<R>(&mut self, mut streams: Vec<CsvStreamReader<R>>) -> ProcessorResult<()>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        let mut records: Vec<_> = streams
+            .iter_mut()
+            .map(|stream| stream.reader.deserialize::<Transaction>())
+            .collect();
+
+        // The next transaction of every input, None once an input is exhausted
+        let mut heads = Vec::with_capacity(records.len());
+        for input in records.iter_mut() {
+            heads.push(self.next_transaction(input).await?);
+        }
+
+        loop {
+            let next = heads
+                .iter()
+                .enumerate()
+                .filter_map(|(index, head)| head.as_ref().map(|tx| (tx.timestamp, index)))
+                .min();
+            let Some((_, index)) = next else {
+                break;
             };
 
-            // Validate amount for Deposits and Withdrawl. This validation also ensures
-            // that we can safely unwrap amount out of the Option
-            //
-            // ** Do not remove this. Removing this may make the WalletActor panic when it
-            // unwraps the amount out of Option.
-            if matches!(tx.tx_type, TransactionType::Deposit | TransactionType::Withdrawal) {
-                let amount = tx.amount.ok_or(ProcessorError::InvalidAmount {
-                    message: format!("invalid amount for tx_id={}", tx.id),
-                })?;
-
-                if amount < Decimal::ZERO {
-                    return Err(ProcessorError::InvalidAmount {
-                        message: format!("invalid amount for tx_id={}", tx.id),
-                    });
-                }
+            let next_head = self.next_transaction(&mut records[index]).await?;
+            if let Some(tx) = std::mem::replace(&mut heads[index], next_head) {
+                self.dispatch(tx).await?;
             }
+        }
 
-            // Find the wallet actor to route this transaction to. All transactions from a client
-            // will always go to the same WalletActor, so that, the client always has a single and
-            // complete state in the system.
-            if let Some(wallet_actor) = self.wallet_actors.get(tx.client as usize % self.actor_count) {
-                // Sending WalletActor the transaction
-                if let Err(e) = wallet_actor.tell(WalletActorMessages::Tx(tx)).await {
-                    eprintln!("Channel Full, increase buffer size and run the test again {}", e);
-                    return Err(ProcessorError::FatalError);
-                }
+        self.flush().await
+    }
+
+    /// Reads the next transaction, skipping the rows that cannot be deserialized. The
+    /// pending batches are sent when the flush interval elapses while waiting for it.
+    async fn next_transaction<S>(&mut self, records: &mut S) -> ProcessorResult<Option<Transaction>>
+    where
+        S: futures::Stream<Item = Result<Transaction, csv_async::Error>> + Unpin,
+    {
+        loop {
+            // Rows already buffered are read without arming the flush timer
+            let result = match (records.next().now_or_never(), self.flush_deadline) {
+                (Some(result), _) => result,
+                (None, Some(deadline)) => tokio::select! {
+                    result = records.next() => result,
+                    _ = tokio::time::sleep_until(deadline) => {
+                        self.flush().await?;
+                        continue;
+                    }
+                },
+                (None, None) => records.next().await,
+            };
+
+            match result {
+                Some(Ok(transaction)) => return Ok(Some(transaction)),
+                Some(Err(e)) => eprintln!("Error deserializing record: {}", e),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Validates a transaction and routes it to the WalletActor owning its client
+    async fn dispatch(&mut self, mut tx: Transaction) -> ProcessorResult<()> {
+        // Bring the amount to the configured precision so balances never carry more
+        // decimal places than what is reported
+        if let Err(e) = self.config.precision.apply(&mut tx) {
+            self.reject(tx, e);
+            return Ok(());
+        }
+
+        // Run the registered validation rules. The built-in amount rule also ensures
+        // that the WalletActor can safely unwrap the amount out of the Option.
+        if let Err(e) = self.config.rules.check(&tx) {
+            self.reject(tx, e);
+            return Ok(());
+        }
+
+        // Sending WalletActor the transaction. This waits while its mailbox is full,
+        // so it only fails when the actor has stopped; the transaction is then kept
+        // as a dead letter.
+        if self.config.batch_size > 1 {
+            self.enqueue(tx).await
+        } else {
+            self.route(tx).await.map_err(Self::actor_stopped)
+        }
+    }
+
+    /// Reports a transaction rejected before reaching its WalletActor
+    fn reject(&self, tx: Transaction, error: ProcessorError) {
+        match &self.error_sink {
+            Some(sink) => {
+                let _ = sink.send(ActorError {
+                    actor: "processor".into(),
+                    message: Some(WalletActorMessages::Tx(tx)),
+                    error,
+                });
             }
+            None => eprintln!("Rejected tx_id={}: {}", tx.id, error),
+        }
+    }
+
+    /// Find the wallet actor to route a client's transactions to. All transactions from
+    /// a client will always go to the same WalletActor, so that, the client always has a
+    /// single and complete state in the system.
+    fn shard(&self, client: ClientId) -> usize {
+        (client % self.actor_count as ClientId) as usize
+    }
+
+    async fn route(&self, tx: Transaction) -> ProcessorResult<()> {
+        match self.wallet_actors.get(self.shard(tx.client)) {
+            Some(wallet_actor) => wallet_actor.actor_ref().tell(WalletActorMessages::Tx(tx)).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Adds a transaction to its WalletActor's batch, sending the batch once it is full
+    async fn enqueue(&mut self, tx: Transaction) -> ProcessorResult<()> {
+        let shard = self.shard(tx.client);
+        let Some(batch) = self.batches.get_mut(shard) else {
+            return Ok(());
+        };
+
+        batch.push(tx);
+        if batch.len() >= self.config.batch_size {
+            self.flush_shard(shard).await?;
+        } else if self.flush_deadline.is_none()
+            && let Some(interval) = self.config.batch_flush_interval
+        {
+            self.flush_deadline = Some(Instant::now() + interval);
+        }
+
+        Ok(())
+    }
+
+    /// Sends every pending batch
+    async fn flush(&mut self) -> ProcessorResult<()> {
+        self.flush_deadline = None;
+        for shard in 0..self.batches.len() {
+            self.flush_shard(shard).await?;
         }
 
         Ok(())
     }
 
-    pub async fn output(&mut self, mut stream: CsvStreamWriter) -> ProcessorResult<()> {
-        for actor in self.wallet_actors.iter() {
-            let (tx, rx) = oneshot::channel();
+    async fn flush_shard(&mut self, shard: usize) -> ProcessorResult<()> {
+        let batch: Vec<_> = std::mem::take(&mut self.batches[shard])
+            .into_iter()
+            .map(WalletActorMessages::Tx)
+            .collect();
+
+        self.wallet_actors[shard]
+            .actor_ref()
+            .tell_batch(batch)
+            .await
+            .map_err(Self::actor_stopped)
+    }
+
+    fn actor_stopped(e: ProcessorError) -> ProcessorError {
+        eprintln!("WalletActor stopped, aborting: {}", e);
+        ProcessorError::FatalError
+    }
+
+    /// Routes the transactions that could not be delivered to their WalletActor again,
+    /// and returns how many were delivered. Those that fail again stay dead letters.
+    pub async fn replay_dead_letters(&mut self) -> ProcessorResult<usize> {
+        let mut replayed = 0;
+        for letter in self.dead_letters.take() {
+            if let WalletActorMessages::Tx(tx) = letter.message
+                && self.route(tx).await.is_ok()
+            {
+                replayed += 1;
+            }
+        }
+
+        Ok(replayed)
+    }
 
-            // Sending command to fetch all the wallets from a WalletActor
-            if let Ok(wallet_state) = actor.ask(WalletActorMessages::Output(tx), rx).await {
-                for wallet in wallet_state {
-                    let wallet_csv_view: WalletCsvView = wallet.into();
+    pub async fn output<W>(&mut self, mut stream: CsvStreamWriter<W>) -> ProcessorResult<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        // Fetching all the wallets from every WalletActor at once. Nothing is written
+        // unless all of them replied.
+        let replies = channel_actor::scatter_gather(
+            self.actor_refs(),
+            WalletActorMessages::Output,
+            self.config.reply_timeout,
+        )
+        .await
+        .complete()?;
+        self.write_wallets(&mut stream, replies).await?;
 
-                    stream.writer.serialize(wallet_csv_view).await.map_err(|e| {
-                        eprintln!("SERDE ERROR: {:?}", e);
-                        ProcessorError::Serialization(e.to_string())
-                    })?;
+        stream
+            .writer
+            .flush()
+            .await
+            .map_err(|e| ProcessorError::Serialization(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Writes the balances as they are right now, without waiting for the transactions
+    /// still queued for the WalletActors.
+    pub async fn balances<W>(&mut self, mut stream: CsvStreamWriter<W>) -> ProcessorResult<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        // Queries go on the control lane, ahead of the queued transactions
+        let replies = channel_actor::scatter_gather_control(
+            self.actor_refs(),
+            WalletActorMessages::Balances,
+            self.config.reply_timeout,
+        )
+        .await
+        .complete()?;
+        self.write_wallets(&mut stream, replies).await?;
+
+        stream
+            .writer
+            .flush()
+            .await
+            .map_err(|e| ProcessorError::Serialization(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Writes one `client,flag` row per fraud flag raised so far. Has to be called
+    /// before `output`, which consumes the wallets.
+    pub async fn fraud_report<W>(&mut self, mut stream: CsvStreamWriter<W>) -> ProcessorResult<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let replies = channel_actor::scatter_gather(
+            self.actor_refs(),
+            WalletActorMessages::FraudReport,
+            self.config.reply_timeout,
+        )
+        .await
+        .complete()?;
+        for (_, flagged) in replies {
+            for flagged_client in flagged {
+                for flag in flagged_client.flags {
+                    let view = FraudCsvView {
+                        client: flagged_client.client,
+                        flag,
+                    };
+                    stream
+                        .writer
+                        .serialize(view)
+                        .await
+                        .map_err(|e| ProcessorError::Serialization(e.to_string()))?;
                 }
             }
         }
@@ -120,4 +453,198 @@ impl TransactionProcessor {
 
         Ok(())
     }
+
+    /// Writes the transactions that were parked waiting for the transaction they
+    /// reference and were given up on. Transactions still parked are only expired
+    /// by `output`, so call this afterwards to get all of them.
+    pub async fn expired_report<W>(&mut self, mut stream: CsvStreamWriter<W>) -> ProcessorResult<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let replies = channel_actor::scatter_gather(
+            self.actor_refs(),
+            WalletActorMessages::Expired,
+            self.config.reply_timeout,
+        )
+        .await
+        .complete()?;
+        for (_, expired) in replies {
+            for expired_tx in expired {
+                let view = ExpiredCsvView {
+                    tx_type: expired_tx.tx.tx_type,
+                    client: expired_tx.tx.client,
+                    tx: expired_tx.tx.id,
+                    amount: expired_tx.tx.amount,
+                    timestamp: expired_tx.tx.timestamp,
+                    reason: expired_tx.reason,
+                };
+                stream
+                    .writer
+                    .serialize(view)
+                    .await
+                    .map_err(|e| ProcessorError::Serialization(e.to_string()))?;
+            }
+        }
+
+        stream
+            .writer
+            .flush()
+            .await
+            .map_err(|e| ProcessorError::Serialization(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Writes one `actor,reason,type,client,tx,amount,timestamp` row per transaction that could
+    /// not be delivered to or handled by its WalletActor. The reported transactions are
+    /// no longer replayed.
+    pub async fn dead_letter_report<W>(&mut self, mut stream: CsvStreamWriter<W>) -> ProcessorResult<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        for letter in self.dead_letters.take() {
+            // Queries are answered to nobody once they are dead, only transactions matter
+            let WalletActorMessages::Tx(tx) = letter.message else {
+                continue;
+            };
+
+            let view = DeadLetterCsvView {
+                actor: letter.actor,
+                reason: letter.reason,
+                tx_type: tx.tx_type,
+                client: tx.client,
+                tx: tx.id,
+                amount: tx.amount,
+                timestamp: tx.timestamp,
+            };
+            stream
+                .writer
+                .serialize(view)
+                .await
+                .map_err(|e| ProcessorError::Serialization(e.to_string()))?;
+        }
+
+        stream
+            .writer
+            .flush()
+            .await
+            .map_err(|e| ProcessorError::Serialization(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Writes one `actor,error,type,client,tx,amount,timestamp` row per transaction a WalletActor
+    /// could not apply, and per row the processor rejected, reported as `processor`. Errors are reported as the actors get to the transactions, so call
+    /// this after `output` to get all of them. Fails unless the config enables the report.
+    pub async fn error_report<W>(&mut self, mut stream: CsvStreamWriter<W>) -> ProcessorResult<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let Some(errors) = &mut self.errors else {
+            return Err(ProcessorError::ErrorReportDisabled);
+        };
+
+        while let Ok(actor_error) = errors.try_recv() {
+            let tx = match actor_error.message {
+                Some(WalletActorMessages::Tx(tx)) => Some(tx),
+                _ => None,
+            };
+            let view = ErrorCsvView {
+                actor: actor_error.actor,
+                error: actor_error.error.to_string(),
+                tx_type: tx.as_ref().map(|tx| tx.tx_type.clone()),
+                client: tx.as_ref().map(|tx| tx.client),
+                tx: tx.as_ref().map(|tx| tx.id),
+                amount: tx.as_ref().and_then(|tx| tx.amount),
+                timestamp: tx.and_then(|tx| tx.timestamp),
+            };
+            stream
+                .writer
+                .serialize(view)
+                .await
+                .map_err(|e| ProcessorError::Serialization(e.to_string()))?;
+        }
+
+        stream
+            .writer
+            .flush()
+            .await
+            .map_err(|e| ProcessorError::Serialization(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Returns the WalletActor failures reported since the last call
+    pub fn failures(&mut self) -> Vec<ActorFailure> {
+        let mut failures = Vec::new();
+        while let Ok(failure) = self.failures.try_recv() {
+            failures.push(failure);
+        }
+
+        failures
+    }
+
+    /// Stops every WalletActor once it has handled the transactions already sent to
+    /// it, and waits for all of them to finish. They are taken out of the registry first.
+    pub async fn shutdown(self) -> ProcessorResult<()> {
+        let mut result = Ok(());
+        for actor in self.wallet_actors {
+            self.registry.unregister(actor.actor_ref().name());
+            if let Err(e) = actor.stop().await {
+                result = Err(e);
+            }
+        }
+
+        result
+    }
+
+    /// Writes the wallets reported by every WalletActor. Each actor that collected fees
+    /// reports a row for the house account, so those rows are merged and written last.
+    async fn write_wallets<W>(
+        &self,
+        stream: &mut CsvStreamWriter<W>,
+        replies: Vec<(String, ProcessorResult<Vec<WalletState>>)>,
+    ) -> ProcessorResult<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let house_account = self.config.fees.house_account;
+        let mut house_state: Option<WalletState> = None;
+
+        for (_, wallet_state) in replies {
+            for wallet in wallet_state? {
+                if wallet.client == house_account {
+                    house_state = Some(match house_state {
+                        Some(merged) => merged.merge(wallet)?,
+                        None => wallet,
+                    });
+                    continue;
+                }
+
+                Self::write_wallet(stream, wallet, &self.config.precision).await?;
+            }
+        }
+
+        if let Some(wallet) = house_state {
+            Self::write_wallet(stream, wallet, &self.config.precision).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write_wallet<W>(
+        stream: &mut CsvStreamWriter<W>,
+        wallet: WalletState,
+        precision: &Precision,
+    ) -> ProcessorResult<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let wallet_csv_view = WalletCsvView::new(wallet, precision);
+
+        stream.writer.serialize(wallet_csv_view).await.map_err(|e| {
+            eprintln!("SERDE ERROR: {:?}", e);
+            ProcessorError::Serialization(e.to_string())
+        })
+    }
 }
@@ -0,0 +1,199 @@
+use std::{fmt, sync::Arc};
+
+use rust_decimal::Decimal;
+
+use crate::{ProcessorError, ProcessorResult, Transaction, TransactionType};
+
+use super::wallet_actor::Wallet;
+
+/// A validation rule run for every transaction. A rule can reject a transaction
+/// before it is routed to its `WalletActor`, and again once the client's wallet
+/// is known. Both checks pass by default, so a rule only implements what it needs.
+pub trait TransactionRule: Send + Sync {
+    /// Checked by the `TransactionProcessor` before routing the transaction
+    fn check(&self, _tx: &Transaction) -> ProcessorResult<()> {
+        Ok(())
+    }
+
+    /// Checked by the wallet right before the transaction is applied to it
+    fn check_wallet(&self, _tx: &Transaction, _wallet: &Wallet) -> ProcessorResult<()> {
+        Ok(())
+    }
+}
+
+/// Validates the amount column for every transaction type. This also guarantees
+/// that the wallet can safely unwrap the amount of deposits and withdrawals.
+/// Deposits and withdrawals must be positive and within `[min, max]` when set.
+///
+/// ** Always part of a `RuleSet`. Removing it may make the WalletActor panic when it
+/// unwraps the amount out of Option.
+#[derive(Clone, Debug, Default)]
+pub struct AmountRule {
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+}
+
+impl TransactionRule for AmountRule {
+    fn check(&self, tx: &Transaction) -> ProcessorResult<()> {
+        let valid = match tx.tx_type {
+            TransactionType::Deposit | TransactionType::Withdrawal => tx.amount.is_some_and(|amount| {
+                amount > Decimal::ZERO
+                    && self.min.is_none_or(|min| amount >= min)
+                    && self.max.is_none_or(|max| amount <= max)
+            }),
+            // Overdraft limits set through admin transactions cannot be negative
+            TransactionType::Overdraft => tx.amount.is_some_and(|amount| amount >= Decimal::ZERO),
+            // Disputes, chargebacks and refunds may optionally carry the portion of the
+            // original amount they apply to. When present, it has to be positive.
+            TransactionType::Dispute | TransactionType::Chargeback | TransactionType::Refund => {
+                tx.amount.is_none_or(|amount| amount > Decimal::ZERO)
+            }
+            TransactionType::Resolve => true,
+        };
+
+        if !valid {
+            return Err(ProcessorError::InvalidAmount {
+                message: format!("invalid amount for tx_id={}", tx.id),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// The rules evaluated by a processor and its wallets. The built-in amount rule
+/// always runs first, then the registered rules in registration order.
+#[derive(Clone, Default)]
+pub struct RuleSet {
+    pub amount: AmountRule,
+    rules: Vec<Arc<dyn TransactionRule>>,
+}
+
+impl fmt::Debug for RuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RuleSet")
+            .field("amount", &self.amount)
+            .field("rules", &self.rules.len())
+            .finish()
+    }
+}
+
+impl RuleSet {
+    pub fn register(&mut self, rule: Arc<dyn TransactionRule>) {
+        self.rules.push(rule);
+    }
+
+    pub fn check(&self, tx: &Transaction) -> ProcessorResult<()> {
+        self.amount.check(tx)?;
+        self.rules.iter().try_for_each(|rule| rule.check(tx))
+    }
+
+    pub fn check_wallet(&self, tx: &Transaction, wallet: &Wallet) -> ProcessorResult<()> {
+        self.amount.check_wallet(tx, wallet)?;
+        self.rules.iter().try_for_each(|rule| rule.check_wallet(tx, wallet))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_tx(tx_type: TransactionType, amount: Option<Decimal>) -> Transaction {
+        Transaction {
+            id: 1,
+            client: 1,
+            tx_type,
+            amount,
+            timestamp: None,
+            disputed: false,
+            held: Decimal::ZERO,
+            charged_back: Decimal::ZERO,
+            refunded: Decimal::ZERO,
+            fee: Decimal::ZERO,
+            processed_at: None,
+        }
+    }
+
+    #[test]
+    fn amount_rule_validates_amount_per_transaction_type() {
+        let rules = RuleSet::default();
+
+        assert!(
+            rules
+                .check(&make_tx(TransactionType::Deposit, Some(Decimal::ONE)))
+                .is_ok()
+        );
+        assert!(rules.check(&make_tx(TransactionType::Deposit, None)).is_err());
+        assert!(
+            rules
+                .check(&make_tx(TransactionType::Withdrawal, Some(-Decimal::ONE)))
+                .is_err()
+        );
+        assert!(rules.check(&make_tx(TransactionType::Dispute, None)).is_ok());
+        assert!(
+            rules
+                .check(&make_tx(TransactionType::Chargeback, Some(Decimal::ZERO)))
+                .is_err()
+        );
+        assert!(rules.check(&make_tx(TransactionType::Overdraft, None)).is_err());
+    }
+
+    #[test]
+    fn amount_rule_rejects_zero_and_out_of_bounds_amounts() {
+        let rules = RuleSet {
+            amount: AmountRule {
+                min: Some(Decimal::ONE),
+                max: Some(Decimal::ONE_HUNDRED),
+            },
+            ..Default::default()
+        };
+
+        assert!(
+            rules
+                .check(&make_tx(TransactionType::Deposit, Some(Decimal::ZERO)))
+                .is_err()
+        );
+        assert!(
+            rules
+                .check(&make_tx(TransactionType::Deposit, Some(Decimal::new(5, 1))))
+                .is_err()
+        );
+        assert!(
+            rules
+                .check(&make_tx(TransactionType::Withdrawal, Some(Decimal::ONE_THOUSAND)))
+                .is_err()
+        );
+        assert!(
+            rules
+                .check(&make_tx(TransactionType::Withdrawal, Some(Decimal::TEN)))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn registered_rules_run_after_built_in_rules() {
+        struct NoWithdrawalsWhileHeld;
+
+        impl TransactionRule for NoWithdrawalsWhileHeld {
+            fn check_wallet(&self, tx: &Transaction, wallet: &Wallet) -> ProcessorResult<()> {
+                if tx.tx_type == TransactionType::Withdrawal && wallet.held > Decimal::ZERO {
+                    return Err(ProcessorError::InvalidTransaction {
+                        message: "withdrawal while funds are held".to_string(),
+                    });
+                }
+                Ok(())
+            }
+        }
+
+        let mut rules = RuleSet::default();
+        rules.register(Arc::new(NoWithdrawalsWhileHeld));
+
+        let withdrawal = make_tx(TransactionType::Withdrawal, Some(Decimal::ONE));
+        let mut wallet = Wallet::default();
+        wallet.held = Decimal::ONE;
+
+        assert!(rules.check(&withdrawal).is_ok());
+        assert!(rules.check_wallet(&withdrawal, &wallet).is_err());
+        assert!(rules.check_wallet(&withdrawal, &Wallet::default()).is_ok());
+    }
+}
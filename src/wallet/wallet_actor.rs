@@ -1,13 +1,48 @@
 use rust_decimal::Decimal;
-use std::collections::HashMap;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
 use tokio::sync::oneshot;
 
-use crate::{ProcessorError, ProcessorResult, Transaction, TransactionType, channel_actor::ChannelActor};
+use crate::{
+    ClientId, ProcessorError, ProcessorResult, Transaction, TransactionType, TxId,
+    channel_actor::{ChannelActor, Recoverable},
+};
 
+use super::{
+    config::WalletConfig,
+    fraud::{FraudFlag, FraudState},
+    limits::{self, VelocityState},
+};
+
+/// What a WalletActor handles. The reports are answered on the sender they carry.
 #[derive(Debug)]
-pub(crate) enum WalletActorMessages {
+pub enum WalletActorMessages {
     Tx(Transaction),
-    Output(oneshot::Sender<Vec<WalletState>>),
+    Output(oneshot::Sender<ProcessorResult<Vec<WalletState>>>),
+    // Copy of the wallets as they are now, answered on the control lane
+    Balances(oneshot::Sender<ProcessorResult<Vec<WalletState>>>),
+    FraudReport(oneshot::Sender<Vec<FlaggedClient>>),
+    Expired(oneshot::Sender<Vec<ExpiredTransaction>>),
+}
+
+/// Why a parked transaction was given up on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpiryReason {
+    // Evicted to make room for a newer one in the client's reorder buffer
+    BufferFull,
+    // The referenced transaction never arrived
+    Unmatched,
+}
+
+/// A parked transaction that was given up on
+#[derive(Clone, Debug)]
+pub struct ExpiredTransaction {
+    pub tx: Transaction,
+    pub reason: ExpiryReason,
 }
 
 #[derive(Clone, Default, Debug)]
@@ -17,44 +52,157 @@ pub struct Wallet {
     pub total: Decimal,
     pub locked: bool,
     // Store transaction history for disputes
-    pub transactions: HashMap<u32, Transaction>,
+    pub transactions: HashMap<TxId, Transaction>,
+    // Fees charged to this wallet so far, credited to the house account as they are charged
+    pub fees_paid: Decimal,
+    // How far `available` may go below zero through withdrawals
+    pub overdraft_limit: Decimal,
+    // Recent activity tracked for the velocity limits
+    pub velocity: VelocityState,
+    // Suspicious patterns detected so far
+    pub fraud: FraudState,
+    // Latest event time applied to this wallet
+    pub last_timestamp: Option<u64>,
+    config: Arc<WalletConfig>,
+}
+
+/// A client with the fraud flags raised on its wallet
+#[derive(Debug)]
+pub struct FlaggedClient {
+    pub client: ClientId,
+    pub flags: Vec<FraudFlag>,
 }
 
+/// A client's wallet as reported by its WalletActor
 #[derive(Debug)]
-pub(crate) struct WalletState {
-    pub client: u16,
+pub struct WalletState {
+    pub client: ClientId,
     pub wallet: Wallet,
 }
 
+impl WalletState {
+    /// The state reported for `client`, with the wallet's total worked out
+    fn new(client: ClientId, mut wallet: Wallet) -> ProcessorResult<Self> {
+        wallet.total = add_balances(client, wallet.available, wallet.held)?;
+        Ok(Self { client, wallet })
+    }
+
+    /// Combines the balances of two states reported for the same client
+    pub(crate) fn merge(mut self, other: WalletState) -> ProcessorResult<Self> {
+        let client = self.client;
+        self.wallet.available = add_balances(client, self.wallet.available, other.wallet.available)?;
+        self.wallet.held = add_balances(client, self.wallet.held, other.wallet.held)?;
+        self.wallet.total = add_balances(client, self.wallet.total, other.wallet.total)?;
+        self.wallet.locked |= other.wallet.locked;
+        Ok(self)
+    }
+}
+
+fn add_balances(client: ClientId, lhs: Decimal, rhs: Decimal) -> ProcessorResult<Decimal> {
+    lhs.checked_add(rhs).ok_or(ProcessorError::BalanceOverflow { client })
+}
+
 impl Wallet {
+    pub fn new(config: Arc<WalletConfig>) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
     pub fn process_transaction(&mut self, tx: Transaction) -> ProcessorResult<()> {
-        // If the wallet is locked, then no deposits and withdrawals are allowed
-        if self.locked && matches!(tx.tx_type, TransactionType::Deposit | TransactionType::Withdrawal) {
+        // If the wallet is locked, then no deposits, withdrawals, refunds and overdraft
+        // changes are allowed
+        if self.locked
+            && matches!(
+                tx.tx_type,
+                TransactionType::Deposit
+                    | TransactionType::Withdrawal
+                    | TransactionType::Refund
+                    | TransactionType::Overdraft
+            )
+        {
             return Err(ProcessorError::AccountLocked { client: tx.client });
         }
 
-        match tx.tx_type {
+        let config = self.config.clone();
+        config.rules.check_wallet(&tx, self)?;
+
+        if let (Some(timestamp), Some(last)) = (tx.timestamp, self.last_timestamp)
+            && config.monotonic_timestamps
+            && timestamp < last
+        {
+            return Err(ProcessorError::NonMonotonicTimestamp {
+                client: tx.client,
+                tx_id: tx.id,
+                timestamp,
+                last,
+            });
+        }
+
+        // Velocity windows follow event time when the input has it
+        let now = tx.timestamp.or(tx.processed_at).unwrap_or_else(limits::now_secs);
+        let timestamp = tx.timestamp;
+        let velocity_limits = config.velocity.limits_for(tx.client);
+        self.velocity.check(velocity_limits, &tx, now)?;
+        let (tx_type, tx_id, amount) = (tx.tx_type.clone(), tx.id, tx.amount);
+
+        let result = match tx.tx_type {
             TransactionType::Deposit => self.handle_deposit(tx),
             TransactionType::Withdrawal => self.handle_withdrawl(tx),
-            TransactionType::Dispute => self.handle_dispute(tx.id),
+            TransactionType::Dispute => self.handle_dispute(tx.id, tx.amount),
             TransactionType::Resolve => self.handle_resolve(tx.id),
-            TransactionType::Chargeback => self.handle_chargeback(tx.id),
+            TransactionType::Chargeback => self.handle_chargeback(tx.id, tx.amount),
+            TransactionType::Refund => self.handle_refund(tx.id, tx.amount),
+            TransactionType::Overdraft => self.handle_overdraft(tx),
+        };
+
+        if result.is_ok() {
+            self.velocity.record(velocity_limits, &tx_type, amount, now);
+            self.last_timestamp = self.last_timestamp.max(timestamp);
+            config.fraud.assess(self, &tx_type, tx_id, amount);
         }
+
+        result
     }
 
-    fn handle_deposit(&mut self, tx: Transaction) -> ProcessorResult<()> {
+    /// Checks that `required` can be taken out of `available`, using the overdraft
+    /// limit on top of it. Takes the balances rather than `&self` so it can be used
+    /// while a stored transaction is borrowed.
+    fn ensure_funds(available: Decimal, limit: Decimal, required: Decimal) -> ProcessorResult<()> {
+        // A sum too large to represent is more than enough to cover `required`
+        let spendable = available.checked_add(limit).unwrap_or(Decimal::MAX);
+        if spendable < required {
+            return Err(ProcessorError::InsufficientFunds {
+                available,
+                required,
+                limit,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn handle_deposit(&mut self, mut tx: Transaction) -> ProcessorResult<()> {
         if self.transactions.contains_key(&tx.id) {
             return Err(ProcessorError::DuplicateTransaction { tx_id: tx.id });
         }
 
         // Safe unwrap as validation done earlier in Processor
-        self.available += tx.amount.unwrap();
+        let amount = tx.amount.unwrap();
+
+        // The deposit fee is taken out of the deposited amount, never beyond it
+        tx.fee = self.config.precision.round(self.config.fees.fee_for(&tx)?).min(amount);
+        let available = checked_add(self.available, amount - tx.fee, tx.id)?;
+        let fees_paid = checked_add(self.fees_paid, tx.fee, tx.id)?;
 
+        self.available = available;
+        self.fees_paid = fees_paid;
         self.transactions.insert(tx.id, tx);
         Ok(())
     }
 
-    fn handle_withdrawl(&mut self, tx: Transaction) -> ProcessorResult<()> {
+    fn handle_withdrawl(&mut self, mut tx: Transaction) -> ProcessorResult<()> {
         if self.transactions.contains_key(&tx.id) {
             return Err(ProcessorError::DuplicateTransaction { tx_id: tx.id });
         }
@@ -62,44 +210,64 @@ impl Wallet {
         // Safe unwrap as validation done earlier in Processor
         let amount = tx.amount.unwrap();
 
-        if self.available < amount {
-            return Err(ProcessorError::InsufficientFunds {
-                available: self.available,
-                required: amount,
-            });
-        }
+        // The withdrawal fee is charged on top of the withdrawn amount
+        tx.fee = self.config.precision.round(self.config.fees.fee_for(&tx)?);
+        let required = checked_add(amount, tx.fee, tx.id)?;
+
+        Self::ensure_funds(self.available, self.overdraft_limit, required)?;
 
-        self.available -= amount;
+        let available = checked_sub(self.available, required, tx.id)?;
+        let fees_paid = checked_add(self.fees_paid, tx.fee, tx.id)?;
+
+        self.available = available;
+        self.fees_paid = fees_paid;
         self.transactions.insert(tx.id, tx);
         Ok(())
     }
 
-    fn handle_dispute(&mut self, tx_id: u32) -> ProcessorResult<()> {
+    /// Holds `amount` of the referenced transaction, or everything that is still
+    /// disputable when the dispute row carries no amount.
+    fn handle_dispute(&mut self, tx_id: TxId, amount: Option<Decimal>) -> ProcessorResult<()> {
         let tx = self
             .transactions
             .get_mut(&tx_id)
             .ok_or(ProcessorError::TransactionNotFound { tx_id })?;
 
-        tx.disputed = true;
-        // Safe unwrap as validation done earlier in Processor
-        let amount = tx.amount.unwrap();
+        let disputable = tx.remaining();
+        if disputable <= Decimal::ZERO {
+            return Err(ProcessorError::InvalidDisputeState); // Nothing left to dispute
+        }
 
-        match tx.tx_type {
+        let amount = amount.unwrap_or(disputable);
+        if amount > disputable {
+            return Err(ProcessorError::AmountExceedsRemaining {
+                tx_id,
+                remaining: disputable,
+                requested: amount,
+            });
+        }
+
+        let (available, held) = match tx.tx_type {
             TransactionType::Deposit => {
                 // We are allowing negative wallet balance
-                self.available -= amount;
-                self.held += amount;
-            }
-            TransactionType::Withdrawal => {
-                self.held += amount;
+                (
+                    checked_sub(self.available, amount, tx_id)?,
+                    checked_add(self.held, amount, tx_id)?,
+                )
             }
-            _ => {} // NoOp, as we keep track of deposits and withdrawls only
-        }
+            TransactionType::Withdrawal => (self.available, checked_add(self.held, amount, tx_id)?),
+            _ => (self.available, self.held), // NoOp, as we keep track of deposits and withdrawls only
+        };
+
+        tx.held += amount;
+        tx.disputed = true;
+        self.available = available;
+        self.held = held;
 
         Ok(())
     }
 
-    fn handle_resolve(&mut self, tx_id: u32) -> ProcessorResult<()> {
+    fn handle_resolve(&mut self, tx_id: TxId) -> ProcessorResult<()> {
         let tx = self
             .transactions
             .get_mut(&tx_id)
@@ -109,25 +277,29 @@ impl Wallet {
             return Err(ProcessorError::InvalidDisputeState); // Ignore if not disputed
         }
 
-        tx.disputed = false;
-        // Safe unwrap as validation done earlier in Processor
-        let amount = tx.amount.unwrap();
+        // Release everything still held for this transaction
+        let amount = tx.held;
 
-        match tx.tx_type {
-            TransactionType::Deposit => {
-                self.held -= amount;
-                self.available += amount;
-            }
-            TransactionType::Withdrawal => {
-                self.held -= amount;
-            }
-            _ => {} // NoOp, as we keep track of deposits and withdrawls only
-        }
+        let (available, held) = match tx.tx_type {
+            TransactionType::Deposit => (
+                checked_add(self.available, amount, tx_id)?,
+                checked_sub(self.held, amount, tx_id)?,
+            ),
+            TransactionType::Withdrawal => (self.available, checked_sub(self.held, amount, tx_id)?),
+            _ => (self.available, self.held), // NoOp, as we keep track of deposits and withdrawls only
+        };
+
+        tx.held = Decimal::ZERO;
+        tx.disputed = false;
+        self.available = available;
+        self.held = held;
 
         Ok(())
     }
 
-    fn handle_chargeback(&mut self, tx_id: u32) -> ProcessorResult<()> {
+    /// Reverses `amount` of the held portion, or all of it when the chargeback row
+    /// carries no amount. Whatever remains held stays under dispute.
+    fn handle_chargeback(&mut self, tx_id: TxId, amount: Option<Decimal>) -> ProcessorResult<()> {
         let tx = self
             .transactions
             .get_mut(&tx_id)
@@ -137,37 +309,219 @@ impl Wallet {
             return Err(ProcessorError::InvalidDisputeState); // Ignore if not disputed
         }
 
-        tx.disputed = false;
-        // Safe unwrap as validation done earlier in Processor
-        let amount = tx.amount.unwrap();
+        let amount = amount.unwrap_or(tx.held);
+        if amount > tx.held {
+            return Err(ProcessorError::AmountExceedsRemaining {
+                tx_id,
+                remaining: tx.held,
+                requested: amount,
+            });
+        }
+
+        let (available, held) = match tx.tx_type {
+            TransactionType::Deposit => (self.available, checked_sub(self.held, amount, tx_id)?),
+            TransactionType::Withdrawal => (
+                checked_add(self.available, amount, tx_id)?,
+                checked_sub(self.held, amount, tx_id)?,
+            ),
+            _ => return Ok(()),
+        };
+
+        tx.held -= amount;
+        tx.charged_back += amount;
+        tx.disputed = tx.held > Decimal::ZERO;
+        self.available = available;
+        self.held = held;
+        self.locked = true;
+
+        Ok(())
+    }
+
+    /// Undoes `amount` of the referenced transaction, or everything that is still
+    /// remaining when the refund row carries no amount. Unlike a chargeback, a refund
+    /// does not lock the account.
+    fn handle_refund(&mut self, tx_id: TxId, amount: Option<Decimal>) -> ProcessorResult<()> {
+        let tx = self
+            .transactions
+            .get_mut(&tx_id)
+            .ok_or(ProcessorError::TransactionNotFound { tx_id })?;
+
+        let remaining = tx.remaining();
+        let amount = amount.unwrap_or(remaining);
+        if amount <= Decimal::ZERO || amount > remaining {
+            return Err(ProcessorError::AmountExceedsRemaining {
+                tx_id,
+                remaining,
+                requested: amount,
+            });
+        }
 
-        match tx.tx_type {
+        let available = match tx.tx_type {
             TransactionType::Deposit => {
-                self.held -= amount;
-                self.locked = true;
+                // Money has to be available to give it back
+                Self::ensure_funds(self.available, self.overdraft_limit, amount)?;
+                checked_sub(self.available, amount, tx_id)?
             }
-            TransactionType::Withdrawal => {
-                self.held -= amount;
-                self.available += amount;
-                self.locked = true;
-            }
-            _ => {}
-        }
+            TransactionType::Withdrawal => checked_add(self.available, amount, tx_id)?,
+            _ => self.available, // NoOp, as we keep track of deposits and withdrawls only
+        };
 
+        tx.refunded += amount;
+        self.available = available;
         Ok(())
     }
+
+    /// Sets the overdraft limit from an admin row, only when the config accepts them
+    fn handle_overdraft(&mut self, tx: Transaction) -> ProcessorResult<()> {
+        let Some(max) = self.config.admin_overdraft_max else {
+            return Err(ProcessorError::InvalidTransaction {
+                message: format!("overdraft rows are not accepted, tx_id={}", tx.id),
+            });
+        };
+
+        match tx.amount {
+            Some(limit) if limit >= Decimal::ZERO && limit <= max => {
+                self.overdraft_limit = limit;
+                Ok(())
+            }
+            _ => Err(ProcessorError::InvalidAmount {
+                message: format!("overdraft limit has to be between 0 and {} for tx_id={}", max, tx.id),
+            }),
+        }
+    }
+}
+
+fn checked_add(lhs: Decimal, rhs: Decimal, tx_id: TxId) -> ProcessorResult<Decimal> {
+    lhs.checked_add(rhs).ok_or(ProcessorError::Overflow { tx_id })
+}
+
+fn checked_sub(lhs: Decimal, rhs: Decimal, tx_id: TxId) -> ProcessorResult<Decimal> {
+    lhs.checked_sub(rhs).ok_or(ProcessorError::Overflow { tx_id })
 }
 
 pub(crate) struct WalletActor {
-    wallets: HashMap<u16, Wallet>,
+    wallets: HashMap<ClientId, Wallet>,
+    config: Arc<WalletConfig>,
+    // Per-client reorder buffer of transactions referencing a tx id not seen yet
+    parked: HashMap<ClientId, VecDeque<Transaction>>,
+    expired: Vec<ExpiredTransaction>,
+    // Errors of parked transactions applied along with another one
+    released_errors: Vec<(WalletActorMessages, ProcessorError)>,
+}
+
+// Released errors are taken after every message, so snapshots start without them
+impl Clone for WalletActor {
+    fn clone(&self) -> Self {
+        Self {
+            wallets: self.wallets.clone(),
+            config: self.config.clone(),
+            parked: self.parked.clone(),
+            expired: self.expired.clone(),
+            released_errors: Vec::new(),
+        }
+    }
 }
 
 impl WalletActor {
-    pub(crate) fn create() -> Self {
+    pub(crate) fn create(config: Arc<WalletConfig>) -> Self {
         Self {
             wallets: HashMap::new(),
+            config,
+            parked: HashMap::new(),
+            expired: Vec::new(),
+            released_errors: Vec::new(),
+        }
+    }
+
+    fn apply(&mut self, tx: Transaction) -> ProcessorResult<()> {
+        let (client, tx_id) = (tx.client, tx.id);
+        let opens_tx = matches!(tx.tx_type, TransactionType::Deposit | TransactionType::Withdrawal);
+
+        // Keep a copy of rows referencing another transaction, in case that one has
+        // not arrived yet
+        let parkable = (self.config.reorder_buffer_size > 0
+            && matches!(
+                tx.tx_type,
+                TransactionType::Dispute
+                    | TransactionType::Resolve
+                    | TransactionType::Chargeback
+                    | TransactionType::Refund
+            ))
+        .then(|| tx.clone());
+
+        let result = self.wallet_mut(client).process_transaction(tx);
+        match (result, parkable) {
+            (Err(ProcessorError::TransactionNotFound { .. }), Some(tx)) => {
+                self.park(tx);
+                Ok(())
+            }
+            (Ok(()), _) if opens_tx => {
+                self.credit_fee(client, tx_id)?;
+                self.release_parked(client, tx_id);
+                Ok(())
+            }
+            (result, _) => result,
+        }
+    }
+
+    /// Credits the fee charged for `tx_id` to the house account. The house account may
+    /// live on another actor too; the processor merges the rows reported for it.
+    fn credit_fee(&mut self, client: ClientId, tx_id: TxId) -> ProcessorResult<()> {
+        let fee = self.wallets[&client].transactions[&tx_id].fee;
+        if fee > Decimal::ZERO {
+            let house = self.wallet_mut(self.config.fees.house_account);
+            house.available = checked_add(house.available, fee, tx_id)?;
+        }
+
+        Ok(())
+    }
+
+    fn park(&mut self, tx: Transaction) {
+        let queue = self.parked.entry(tx.client).or_default();
+        if queue.len() >= self.config.reorder_buffer_size
+            && let Some(oldest) = queue.pop_front()
+        {
+            self.expired.push(ExpiredTransaction {
+                tx: oldest,
+                reason: ExpiryReason::BufferFull,
+            });
+        }
+
+        queue.push_back(tx);
+    }
+
+    /// Applies, in arrival order, the parked transactions referencing `tx_id`
+    fn release_parked(&mut self, client: ClientId, tx_id: TxId) {
+        let Some(queue) = self.parked.get_mut(&client) else {
+            return;
+        };
+
+        let (ready, waiting): (VecDeque<_>, VecDeque<_>) =
+            std::mem::take(queue).into_iter().partition(|tx| tx.id == tx_id);
+        if waiting.is_empty() {
+            self.parked.remove(&client);
+        } else {
+            *queue = waiting;
+        }
+
+        for tx in ready {
+            // Reported like the error of the transaction that released them
+            let copy = tx.clone();
+            if let Err(e) = self.wallet_mut(client).process_transaction(tx) {
+                self.released_errors.push((WalletActorMessages::Tx(copy), e));
+            }
         }
     }
+
+    /// Returns the client's wallet, opening it with the configured overdraft limit
+    fn wallet_mut(&mut self, client: ClientId) -> &mut Wallet {
+        let config = &self.config;
+        self.wallets.entry(client).or_insert_with(|| {
+            let mut wallet = Wallet::new(config.clone());
+            wallet.overdraft_limit = config.overdraft_limit(client);
+            wallet
+        })
+    }
 }
 
 #[async_trait::async_trait]
@@ -177,40 +531,106 @@ impl ChannelActor<WalletActorMessages> for WalletActor {
 
         match msg {
             Tx(tx) => {
-                let wallet = self.wallets.entry(tx.client).or_insert_with(|| Wallet::default());
-
-                wallet.process_transaction(tx)?;
+                self.apply(tx)?;
             }
 
             Output(sender) => {
+                // Whatever is still parked will never find its transaction
+                let unmatched = std::mem::take(&mut self.parked).into_values().flatten();
+                self.expired.extend(unmatched.map(|tx| ExpiredTransaction {
+                    tx,
+                    reason: ExpiryReason::Unmatched,
+                }));
+
                 // Consume the state as we have finished processing the transactions
-                let state: Vec<WalletState> = std::mem::take(&mut self.wallets)
+                let state: ProcessorResult<Vec<WalletState>> = std::mem::take(&mut self.wallets)
                     .into_iter()
-                    .map(|(client, mut wallet)| {
-                        wallet.total = wallet.available + wallet.held;
-                        WalletState { client, wallet }
-                    })
+                    .map(|(client, wallet)| WalletState::new(client, wallet))
                     .collect();
                 let _ = sender.send(state);
             }
+
+            Balances(sender) => {
+                let state: ProcessorResult<Vec<WalletState>> = self
+                    .wallets
+                    .iter()
+                    .map(|(client, wallet)| WalletState::new(*client, wallet.clone()))
+                    .collect();
+                let _ = sender.send(state);
+            }
+
+            FraudReport(sender) => {
+                let flagged: Vec<FlaggedClient> = self
+                    .wallets
+                    .iter()
+                    .filter(|(_, wallet)| !wallet.fraud.flags.is_empty())
+                    .map(|(client, wallet)| FlaggedClient {
+                        client: *client,
+                        flags: wallet.fraud.flags.iter().copied().collect(),
+                    })
+                    .collect();
+                let _ = sender.send(flagged);
+            }
+
+            Expired(sender) => {
+                let _ = sender.send(std::mem::take(&mut self.expired));
+            }
         }
 
         Ok(())
     }
+
+    fn take_errors(&mut self) -> Vec<(WalletActorMessages, ProcessorError)> {
+        std::mem::take(&mut self.released_errors)
+    }
+
+    // Failing transactions are reported with the row they came from
+    fn error_context(msg: &WalletActorMessages) -> Option<WalletActorMessages> {
+        match msg {
+            WalletActorMessages::Tx(tx) => Some(WalletActorMessages::Tx(tx.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl Recoverable<WalletActorMessages> for WalletActor {
+    // Only transactions are replayed; the reports are followed by a fresh snapshot.
+    // Without a timestamp, the replay uses the processing time of the first run.
+    fn journal(msg: &mut WalletActorMessages) -> Option<WalletActorMessages> {
+        match msg {
+            WalletActorMessages::Tx(tx) => {
+                tx.processed_at.get_or_insert_with(limits::now_secs);
+                Some(WalletActorMessages::Tx(tx.clone()))
+            }
+            _ => None,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::wallet::{
+        fees::{FeeKind, FeeRule, FeeSchedule},
+        fraud::FraudConfig,
+        limits::{LimitRule, TransactionWindow, VelocityConfig, VelocityLimits},
+    };
     use rust_decimal::{Decimal, prelude::FromPrimitive};
+    use std::time::Duration;
 
-    fn make_tx(id: u32, client: u16, tx_type: TransactionType, amount: Option<Decimal>) -> Transaction {
+    fn make_tx(id: TxId, client: ClientId, tx_type: TransactionType, amount: Option<Decimal>) -> Transaction {
         Transaction {
             id,
             client,
             tx_type,
             amount,
+            timestamp: None,
             disputed: false,
+            held: Decimal::ZERO,
+            charged_back: Decimal::ZERO,
+            refunded: Decimal::ZERO,
+            fee: Decimal::ZERO,
+            processed_at: None,
         }
     }
 
@@ -264,9 +684,14 @@ mod tests {
             .unwrap_err();
 
         match err {
-            ProcessorError::InsufficientFunds { available, required } => {
+            ProcessorError::InsufficientFunds {
+                available,
+                required,
+                limit,
+            } => {
                 assert_eq!(Some(available), Decimal::from_f32(20.0));
                 assert_eq!(Some(required), Decimal::from_f32(50.0));
+                assert_eq!(limit, Decimal::ZERO);
             }
             _ => panic!("Expected InsufficientFunds error"),
         }
@@ -346,4 +771,607 @@ mod tests {
             .unwrap_err();
         assert!(matches!(withdrawal_err, ProcessorError::AccountLocked { .. }));
     }
+
+    #[test]
+    fn partial_dispute_holds_only_requested_amount() {
+        let mut wallet = Wallet::default();
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(100.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Dispute, Decimal::from_f32(40.0)))
+            .unwrap();
+
+        assert_eq!(Some(wallet.available), Decimal::from_f32(60.0));
+        assert_eq!(Some(wallet.held), Decimal::from_f32(40.0));
+        assert_eq!(Some(wallet.transactions[&1].remaining()), Decimal::from_f32(60.0));
+    }
+
+    #[test]
+    fn dispute_exceeding_remaining_amount_fails() {
+        let mut wallet = Wallet::default();
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(100.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Dispute, Decimal::from_f32(70.0)))
+            .unwrap();
+
+        let err = wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Dispute, Decimal::from_f32(50.0)))
+            .unwrap_err();
+
+        match err {
+            ProcessorError::AmountExceedsRemaining {
+                tx_id,
+                remaining,
+                requested,
+            } => {
+                assert_eq!(tx_id, 1);
+                assert_eq!(Some(remaining), Decimal::from_f32(30.0));
+                assert_eq!(Some(requested), Decimal::from_f32(50.0));
+            }
+            _ => panic!("Expected AmountExceedsRemaining error"),
+        }
+    }
+
+    #[test]
+    fn partial_chargeback_keeps_rest_under_dispute() {
+        let mut wallet = Wallet::default();
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(100.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Dispute, Decimal::from_f32(60.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Chargeback, Decimal::from_f32(25.0)))
+            .unwrap();
+
+        assert!(wallet.locked);
+        assert_eq!(Some(wallet.available), Decimal::from_f32(40.0));
+        assert_eq!(Some(wallet.held), Decimal::from_f32(35.0));
+
+        // The remaining held portion can still be resolved
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Resolve, None))
+            .unwrap();
+
+        assert_eq!(Some(wallet.available), Decimal::from_f32(75.0));
+        assert_eq!(Some(wallet.held), Decimal::from_f32(0.0));
+        assert_eq!(Some(wallet.transactions[&1].remaining()), Decimal::from_f32(75.0));
+    }
+
+    #[test]
+    fn refund_reverses_deposit_without_locking() {
+        let mut wallet = Wallet::default();
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(100.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Refund, Decimal::from_f32(30.0)))
+            .unwrap();
+
+        assert!(!wallet.locked);
+        assert_eq!(Some(wallet.available), Decimal::from_f32(70.0));
+
+        // Refunding the rest without an amount reverses whatever remains
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Refund, None))
+            .unwrap();
+        assert_eq!(Some(wallet.available), Decimal::from_f32(0.0));
+    }
+
+    #[test]
+    fn refund_reverses_withdrawal() {
+        let mut wallet = Wallet::default();
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(100.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(2, 100, TransactionType::Withdrawal, Decimal::from_f32(40.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(2, 100, TransactionType::Refund, None))
+            .unwrap();
+
+        assert_eq!(Some(wallet.available), Decimal::from_f32(100.0));
+    }
+
+    #[test]
+    fn refund_cannot_exceed_original_amount() {
+        let mut wallet = Wallet::default();
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(100.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Refund, Decimal::from_f32(60.0)))
+            .unwrap();
+
+        let err = wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Refund, Decimal::from_f32(50.0)))
+            .unwrap_err();
+        assert!(matches!(err, ProcessorError::AmountExceedsRemaining { .. }));
+
+        // A disputed portion cannot be refunded either
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Dispute, None))
+            .unwrap();
+        let err = wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Refund, None))
+            .unwrap_err();
+        assert!(matches!(err, ProcessorError::AmountExceedsRemaining { .. }));
+        assert_eq!(Some(wallet.available), Decimal::from_f32(0.0));
+        assert_eq!(Some(wallet.held), Decimal::from_f32(40.0));
+    }
+
+    #[test]
+    fn fees_are_charged_on_deposits_and_withdrawals() {
+        let fees = FeeSchedule::new(0)
+            .with_deposit_fee(FeeRule::new(FeeKind::Flat(Decimal::ONE)))
+            .with_withdrawal_fee(FeeRule::new(FeeKind::Percentage(Decimal::TEN)));
+        let mut wallet = Wallet::new(Arc::new(WalletConfig::default().with_fees(fees)));
+
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(100.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(2, 100, TransactionType::Withdrawal, Decimal::from_f32(50.0)))
+            .unwrap();
+
+        assert_eq!(Some(wallet.available), Decimal::from_f32(44.0));
+        assert_eq!(Some(wallet.fees_paid), Decimal::from_f32(6.0));
+    }
+
+    #[test]
+    fn deposit_fee_is_not_refunded_or_disputed() {
+        let fees = FeeSchedule::new(0).with_deposit_fee(FeeRule::new(FeeKind::Flat(Decimal::ONE)));
+        let config = Arc::new(WalletConfig::default().with_fees(fees));
+
+        let mut wallet = Wallet::new(config.clone());
+        wallet
+            .process_transaction(make_tx(1, 1, TransactionType::Deposit, Decimal::from_f32(100.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(1, 1, TransactionType::Refund, None))
+            .unwrap();
+        assert_eq!(wallet.available, Decimal::ZERO);
+        assert_eq!(Some(wallet.transactions[&1].refunded), Decimal::from_f32(99.0));
+
+        let mut wallet = Wallet::new(config);
+        wallet
+            .process_transaction(make_tx(1, 1, TransactionType::Deposit, Decimal::from_f32(100.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(1, 1, TransactionType::Dispute, None))
+            .unwrap();
+        assert_eq!(wallet.available, Decimal::ZERO);
+        assert_eq!(Some(wallet.held), Decimal::from_f32(99.0));
+    }
+
+    #[tokio::test]
+    async fn fees_are_credited_to_the_house_account_when_charged() {
+        let fees = FeeSchedule::new(0).with_deposit_fee(FeeRule::new(FeeKind::Flat(Decimal::ONE)));
+        let mut actor = WalletActor::create(Arc::new(WalletConfig::default().with_fees(fees)));
+
+        for id in 1..=3 {
+            let tx = make_tx(
+                id,
+                100 + id as ClientId,
+                TransactionType::Deposit,
+                Decimal::from_f32(10.0),
+            );
+            actor.handle(WalletActorMessages::Tx(tx)).await.unwrap();
+        }
+
+        let fees_paid: Decimal = actor.wallets.values().map(|wallet| wallet.fees_paid).sum();
+        assert_eq!(actor.wallets[&0].available, fees_paid);
+        assert_eq!(Some(fees_paid), Decimal::from_f32(3.0));
+    }
+
+    #[test]
+    fn withdrawal_fee_counts_towards_required_funds() {
+        let fees = FeeSchedule::new(0).with_withdrawal_fee(FeeRule::new(FeeKind::Flat(Decimal::ONE)));
+        let mut wallet = Wallet::new(Arc::new(WalletConfig::default().with_fees(fees)));
+
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(50.0)))
+            .unwrap();
+        let err = wallet
+            .process_transaction(make_tx(2, 100, TransactionType::Withdrawal, Decimal::from_f32(50.0)))
+            .unwrap_err();
+
+        match err {
+            ProcessorError::InsufficientFunds { required, .. } => {
+                assert_eq!(Some(required), Decimal::from_f32(51.0));
+            }
+            _ => panic!("Expected InsufficientFunds error"),
+        }
+    }
+
+    #[test]
+    fn overdraft_allows_negative_available_up_to_limit() {
+        let config = WalletConfig::default().with_admin_overdrafts(Decimal::ONE_HUNDRED);
+        let mut wallet = Wallet::new(Arc::new(config));
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Overdraft, Decimal::from_f32(50.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(2, 100, TransactionType::Deposit, Decimal::from_f32(20.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(3, 100, TransactionType::Withdrawal, Decimal::from_f32(60.0)))
+            .unwrap();
+
+        assert_eq!(Some(wallet.available), Decimal::from_f32(-40.0));
+
+        let err = wallet
+            .process_transaction(make_tx(4, 100, TransactionType::Withdrawal, Decimal::from_f32(20.0)))
+            .unwrap_err();
+        match err {
+            ProcessorError::InsufficientFunds { limit, .. } => assert_eq!(Some(limit), Decimal::from_f32(50.0)),
+            _ => panic!("Expected InsufficientFunds error"),
+        }
+    }
+
+    #[test]
+    fn overdraft_rows_are_only_accepted_when_enabled() {
+        let tx = make_tx(1, 100, TransactionType::Overdraft, Decimal::from_f32(50.0));
+        let err = Wallet::default().process_transaction(tx.clone()).unwrap_err();
+        assert!(matches!(err, ProcessorError::InvalidTransaction { .. }));
+
+        let config = WalletConfig::default().with_admin_overdrafts(Decimal::TEN);
+        let mut wallet = Wallet::new(Arc::new(config));
+        for amount in [None, Decimal::from_f32(50.0)] {
+            let err = wallet
+                .process_transaction(make_tx(1, 100, TransactionType::Overdraft, amount))
+                .unwrap_err();
+            assert!(matches!(err, ProcessorError::InvalidAmount { .. }));
+        }
+        assert_eq!(wallet.overdraft_limit, Decimal::ZERO);
+
+        wallet.locked = true;
+        let err = wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Overdraft, Some(Decimal::ONE)))
+            .unwrap_err();
+        assert!(matches!(err, ProcessorError::AccountLocked { .. }));
+        assert_eq!(wallet.overdraft_limit, Decimal::ZERO);
+    }
+
+    fn wallet_with_limits(limits: VelocityLimits) -> Wallet {
+        let velocity = VelocityConfig {
+            default: limits,
+            ..Default::default()
+        };
+        Wallet::new(Arc::new(WalletConfig::default().with_velocity(velocity)))
+    }
+
+    fn assert_limit(err: ProcessorError, expected: LimitRule) {
+        match err {
+            ProcessorError::LimitExceeded { rule, .. } => assert_eq!(rule, expected),
+            _ => panic!("Expected LimitExceeded error"),
+        }
+    }
+
+    #[test]
+    fn withdrawal_above_single_limit_is_rejected() {
+        let mut wallet = wallet_with_limits(VelocityLimits {
+            max_single_withdrawal: Decimal::from_f32(50.0),
+            ..Default::default()
+        });
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(100.0)))
+            .unwrap();
+
+        let err = wallet
+            .process_transaction(make_tx(2, 100, TransactionType::Withdrawal, Decimal::from_f32(60.0)))
+            .unwrap_err();
+        assert_limit(err, LimitRule::MaxSingleWithdrawal);
+        assert_eq!(Some(wallet.available), Decimal::from_f32(100.0));
+    }
+
+    #[test]
+    fn daily_withdrawal_total_is_enforced() {
+        let mut wallet = wallet_with_limits(VelocityLimits {
+            max_daily_withdrawal: Decimal::from_f32(50.0),
+            ..Default::default()
+        });
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(100.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(2, 100, TransactionType::Withdrawal, Decimal::from_f32(30.0)))
+            .unwrap();
+
+        let err = wallet
+            .process_transaction(make_tx(3, 100, TransactionType::Withdrawal, Decimal::from_f32(30.0)))
+            .unwrap_err();
+        assert_limit(err, LimitRule::MaxDailyWithdrawal);
+    }
+
+    #[test]
+    fn transactions_per_window_are_enforced() {
+        let mut wallet = wallet_with_limits(VelocityLimits {
+            max_transactions: Some(TransactionWindow {
+                count: 2,
+                window: Duration::from_secs(3600),
+            }),
+            ..Default::default()
+        });
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(10.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(2, 100, TransactionType::Deposit, Decimal::from_f32(10.0)))
+            .unwrap();
+
+        let err = wallet
+            .process_transaction(make_tx(3, 100, TransactionType::Deposit, Decimal::from_f32(10.0)))
+            .unwrap_err();
+        assert_limit(err, LimitRule::MaxTransactionsPerWindow);
+
+        // Disputes are not money movements and are not rate limited
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Dispute, None))
+            .unwrap();
+    }
+
+    #[test]
+    fn journaled_transactions_replay_with_their_processing_time() {
+        let mut wallet = wallet_with_limits(VelocityLimits {
+            max_transactions: Some(TransactionWindow {
+                count: 1,
+                window: Duration::from_secs(3600),
+            }),
+            ..Default::default()
+        });
+
+        let mut msg = WalletActorMessages::Tx(make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(10.0)));
+        let Some(WalletActorMessages::Tx(entry)) = WalletActor::journal(&mut msg) else {
+            panic!("transactions are journaled");
+        };
+        let WalletActorMessages::Tx(tx) = msg else {
+            unreachable!()
+        };
+        assert!(tx.processed_at.is_some());
+        assert_eq!(entry.processed_at, tx.processed_at);
+
+        // Recorded an hour earlier, so outside the window of the next deposit
+        let mut earlier = make_tx(2, 100, TransactionType::Deposit, Decimal::from_f32(10.0));
+        earlier.processed_at = tx.processed_at.map(|at| at - 3600);
+        wallet.process_transaction(earlier).unwrap();
+        wallet.process_transaction(entry).unwrap();
+    }
+
+    fn wallet_with_fraud(fraud: FraudConfig) -> Wallet {
+        Wallet::new(Arc::new(WalletConfig::default().with_fraud(fraud)))
+    }
+
+    #[test]
+    fn large_withdrawal_right_after_deposit_is_flagged() {
+        let mut wallet = wallet_with_fraud(FraudConfig {
+            large_withdrawal_ratio: Decimal::from_f32(0.9),
+            max_rapid_cycles: Some(1),
+            ..Default::default()
+        });
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(100.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(2, 100, TransactionType::Withdrawal, Decimal::from_f32(50.0)))
+            .unwrap();
+        assert!(wallet.fraud.flags.is_empty());
+
+        wallet
+            .process_transaction(make_tx(3, 100, TransactionType::Deposit, Decimal::from_f32(40.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(4, 100, TransactionType::Withdrawal, Decimal::from_f32(40.0)))
+            .unwrap();
+
+        assert!(wallet.fraud.flags.contains(&FraudFlag::LargeWithdrawalAfterDeposit));
+        assert!(wallet.fraud.flags.contains(&FraudFlag::RapidDepositWithdrawCycles));
+        assert!(!wallet.locked);
+    }
+
+    #[test]
+    fn repeated_chargebacks_freeze_the_account() {
+        let mut wallet = wallet_with_fraud(FraudConfig {
+            max_dispute_ratio: Decimal::from_f32(0.5),
+            max_chargebacks: Some(1),
+            auto_freeze: true,
+            ..Default::default()
+        });
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(10.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(2, 100, TransactionType::Deposit, Decimal::from_f32(10.0)))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Dispute, None))
+            .unwrap();
+        assert!(wallet.fraud.flags.is_empty());
+
+        wallet
+            .process_transaction(make_tx(2, 100, TransactionType::Dispute, None))
+            .unwrap();
+        assert!(wallet.fraud.flags.contains(&FraudFlag::HighDisputeRatio));
+        assert!(wallet.locked);
+
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Chargeback, None))
+            .unwrap();
+        wallet
+            .process_transaction(make_tx(2, 100, TransactionType::Chargeback, None))
+            .unwrap();
+        assert!(wallet.fraud.flags.contains(&FraudFlag::RepeatedChargebacks));
+    }
+
+    #[test]
+    fn resolving_disputes_does_not_reset_the_dispute_ratio() {
+        let mut wallet = wallet_with_fraud(FraudConfig {
+            max_dispute_ratio: Decimal::from_f32(0.5),
+            ..Default::default()
+        });
+        for id in 1..=4 {
+            wallet
+                .process_transaction(make_tx(id, 100, TransactionType::Deposit, Decimal::from_f32(10.0)))
+                .unwrap();
+        }
+
+        for id in 1..=3 {
+            wallet
+                .process_transaction(make_tx(id, 100, TransactionType::Dispute, None))
+                .unwrap();
+            wallet
+                .process_transaction(make_tx(id, 100, TransactionType::Resolve, None))
+                .unwrap();
+        }
+
+        assert!(wallet.fraud.flags.contains(&FraudFlag::HighDisputeRatio));
+    }
+
+    #[test]
+    fn overflowing_deposit_is_rejected_without_changing_balance() {
+        let mut wallet = Wallet::default();
+        wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Some(Decimal::MAX)))
+            .unwrap();
+
+        let err = wallet
+            .process_transaction(make_tx(2, 100, TransactionType::Deposit, Some(Decimal::ONE)))
+            .unwrap_err();
+
+        assert!(matches!(err, ProcessorError::Overflow { tx_id: 2 }));
+        assert_eq!(wallet.available, Decimal::MAX);
+        assert!(!wallet.transactions.contains_key(&2));
+    }
+
+    #[test]
+    fn withdrawals_at_the_limits_of_decimal_do_not_panic() {
+        let mut wallet = Wallet::default();
+        for (id, tx_type) in [
+            (1, TransactionType::Deposit),
+            (2, TransactionType::Withdrawal),
+            (3, TransactionType::Deposit),
+            (4, TransactionType::Withdrawal),
+        ] {
+            wallet
+                .process_transaction(make_tx(id, 100, tx_type, Some(Decimal::MAX)))
+                .unwrap();
+        }
+
+        let mut wallet = wallet_with_limits(VelocityLimits {
+            max_daily_withdrawal: Some(Decimal::MAX),
+            ..Default::default()
+        });
+        for (id, tx_type) in [
+            (1, TransactionType::Deposit),
+            (2, TransactionType::Withdrawal),
+            (3, TransactionType::Deposit),
+        ] {
+            wallet
+                .process_transaction(make_tx(id, 100, tx_type, Some(Decimal::MAX)))
+                .unwrap();
+        }
+        let err = wallet
+            .process_transaction(make_tx(4, 100, TransactionType::Withdrawal, Some(Decimal::ONE)))
+            .unwrap_err();
+        assert!(matches!(err, ProcessorError::Overflow { tx_id: 4 }));
+    }
+
+    #[test]
+    fn overflowing_fee_is_rejected() {
+        let fees = FeeSchedule::new(0).with_deposit_fee(FeeRule::new(FeeKind::Percentage(Decimal::new(15, 1))));
+        let mut wallet = Wallet::new(Arc::new(WalletConfig::default().with_fees(fees)));
+
+        let err = wallet
+            .process_transaction(make_tx(1, 100, TransactionType::Deposit, Some(Decimal::MAX)))
+            .unwrap_err();
+        assert!(matches!(err, ProcessorError::Overflow { tx_id: 1 }));
+        assert_eq!(wallet.available, Decimal::ZERO);
+    }
+
+    #[tokio::test]
+    async fn overflowing_balances_are_reported_as_errors() {
+        let mut actor = WalletActor::create(Arc::new(WalletConfig::default()));
+        // A disputed withdrawal is held without taking it out of `available` again
+        for (id, tx_type, amount) in [
+            (1, TransactionType::Deposit, Decimal::MAX),
+            (2, TransactionType::Withdrawal, Decimal::ONE),
+            (3, TransactionType::Deposit, Decimal::ONE),
+        ] {
+            actor
+                .handle(WalletActorMessages::Tx(make_tx(id, 100, tx_type, Some(amount))))
+                .await
+                .unwrap();
+        }
+
+        let (tx, rx) = oneshot::channel();
+        actor.handle(WalletActorMessages::Balances(tx)).await.unwrap();
+        let state = rx.await.unwrap().unwrap();
+        let merged = WalletState::new(100, state[0].wallet.clone())
+            .unwrap()
+            .merge(state.into_iter().next().unwrap());
+        assert!(matches!(merged, Err(ProcessorError::BalanceOverflow { client: 100 })));
+
+        actor
+            .handle(WalletActorMessages::Tx(make_tx(2, 100, TransactionType::Dispute, None)))
+            .await
+            .unwrap();
+        let (tx, rx) = oneshot::channel();
+        actor.handle(WalletActorMessages::Output(tx)).await.unwrap();
+        assert!(matches!(
+            rx.await.unwrap(),
+            Err(ProcessorError::BalanceOverflow { client: 100 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn errors_of_released_transactions_are_taken() {
+        let mut actor = WalletActor::create(Arc::new(WalletConfig::default().with_reorder_buffer(4)));
+        // The chargeback is parked until its deposit arrives, which is not disputed
+        for tx in [
+            make_tx(1, 100, TransactionType::Chargeback, None),
+            make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(10.0)),
+        ] {
+            actor.handle(WalletActorMessages::Tx(tx)).await.unwrap();
+        }
+
+        let errors = actor.take_errors();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            &errors[0],
+            (WalletActorMessages::Tx(tx), ProcessorError::InvalidDisputeState) if tx.tx_type == TransactionType::Chargeback
+        ));
+        assert!(actor.take_errors().is_empty());
+    }
+
+    #[test]
+    fn out_of_order_timestamps_are_rejected_when_monotonic() {
+        let mut wallet = Wallet::new(Arc::new(WalletConfig::default().with_monotonic_timestamps(true)));
+        let mut deposit = make_tx(1, 100, TransactionType::Deposit, Decimal::from_f32(10.0));
+        deposit.timestamp = Some(200);
+        wallet.process_transaction(deposit).unwrap();
+
+        let mut late = make_tx(2, 100, TransactionType::Deposit, Decimal::from_f32(10.0));
+        late.timestamp = Some(100);
+        let err = wallet.process_transaction(late).unwrap_err();
+        assert!(matches!(
+            err,
+            ProcessorError::NonMonotonicTimestamp {
+                timestamp: 100,
+                last: 200,
+                ..
+            }
+        ));
+
+        // Rows without a timestamp are not checked
+        wallet
+            .process_transaction(make_tx(3, 100, TransactionType::Deposit, Decimal::from_f32(10.0)))
+            .unwrap();
+        assert_eq!(wallet.last_timestamp, Some(200));
+        assert_eq!(wallet.transactions[&1].timestamp, Some(200));
+    }
 }
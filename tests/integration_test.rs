@@ -1,8 +1,21 @@
-use std::io::Cursor;
-
-use csv::{Reader, Writer};
-use krwallet::{wallet::processor::TransactionProcessor, CsvStreamReader, CsvStreamWriter};
+use csv_async::{AsyncReaderBuilder, AsyncWriterBuilder};
+use std::{sync::Arc, time::Duration};
 
+use krwallet::{
+    ClientId, CsvStreamReader, CsvStreamWriter, ProcessorError, ProcessorResult, Transaction,
+    input::CsvDialect,
+    wallet::{
+        config::WalletConfig,
+        fees::{FeeKind, FeeRule, FeeSchedule},
+        fraud::FraudConfig,
+        limits::{VelocityConfig, VelocityLimits},
+        precision::{Precision, Rounding},
+        processor::TransactionProcessor,
+        rules::TransactionRule,
+        wallet_actor::{Wallet, WalletActorMessages},
+    },
+};
+use rust_decimal::Decimal;
 
 #[tokio::test]
 async fn test_basic_transactions() {
@@ -13,18 +26,24 @@ deposit,1,3,2.0
 withdrawal,1,4,1.5
 withdrawal,2,5,3.0"#;
 
-    let reader = CsvStreamReader { reader: Reader::from_reader(Cursor::new(csv_data)) };
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
     let mut processor = TransactionProcessor::new(2, 10).await;
 
-    processor.process(reader).await;
+    processor.process(reader).await.unwrap();
 
     let mut output = Vec::new();
-    let writer = CsvStreamWriter { writer: Writer::from_writer(&mut output) };
-    processor.output(writer).await;
-    
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
     let output_str = String::from_utf8(output).unwrap();
     assert!(output_str.contains("1,1.5000,0.0000,1.5000,false"));
     assert!(output_str.contains("2,2.0000,0.0000,2.0000,false"));
+
+    processor.shutdown().await.unwrap();
 }
 
 #[tokio::test]
@@ -34,14 +53,18 @@ deposit,1,1,10.0
 dispute,1,1,
 resolve,1,1,"#;
 
-    let reader = CsvStreamReader { reader: Reader::from_reader(Cursor::new(csv_data)) };
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
     let mut processor = TransactionProcessor::new(2, 10).await;
-    
-    processor.process(reader).await;
+
+    processor.process(reader).await.unwrap();
 
     let mut output = Vec::new();
-    let writer = CsvStreamWriter { writer: Writer::from_writer(&mut output) };
-    processor.output(writer).await;
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
 
     let output_str = String::from_utf8(output).unwrap();
     assert!(output_str.contains("1,10.0000,0.0000,10.0000,false"));
@@ -54,14 +77,18 @@ deposit,1,1,10.0
 dispute,1,1,
 chargeback,1,1,"#;
 
-    let reader = CsvStreamReader { reader: Reader::from_reader(Cursor::new(csv_data)) };
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
     let mut processor = TransactionProcessor::new(2, 10).await;
-    
-    processor.process(reader).await;
-    
+
+    processor.process(reader).await.unwrap();
+
     let mut output = Vec::new();
-    let writer = CsvStreamWriter { writer: Writer::from_writer(&mut output) };
-    processor.output(writer).await;
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
 
     let output_str = String::from_utf8(output).unwrap();
     assert!(output_str.contains("1,0.0000,0.0000,0.0000,true"));
@@ -73,14 +100,18 @@ async fn test_insufficient_funds() {
 deposit,1,1,5.0
 withdrawal,1,2,10.0"#;
 
-    let reader = CsvStreamReader { reader: Reader::from_reader(Cursor::new(csv_data)) };
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
     let mut processor = TransactionProcessor::new(2, 10).await;
-    
-    processor.process(reader).await;
-    
+
+    processor.process(reader).await.unwrap();
+
     let mut output = Vec::new();
-    let writer = CsvStreamWriter { writer: Writer::from_writer(&mut output) };
-    processor.output(writer).await;
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
 
     let output_str = String::from_utf8(output).unwrap();
     // Withdrawal should be rejected, balance remains 5.0
@@ -95,16 +126,649 @@ dispute,1,1,
 chargeback,1,1,
 deposit,1,2,5.0"#;
 
-    let reader = CsvStreamReader { reader: Reader::from_reader(Cursor::new(csv_data)) };
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
     let mut processor = TransactionProcessor::new(2, 10).await;
-    
-    processor.process(reader).await;
-    
+
+    processor.process(reader).await.unwrap();
+
     let mut output = Vec::new();
-    let writer = CsvStreamWriter { writer: Writer::from_writer(&mut output) };
-    processor.output(writer).await;
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
 
     let output_str = String::from_utf8(output).unwrap();
     // Account should be locked, second deposit rejected
     assert!(output_str.contains("1,0.0000,0.0000,0.0000,true"));
 }
+
+#[tokio::test]
+async fn test_partial_dispute_and_chargeback() {
+    let csv_data = r#"type,client,tx,amount
+deposit,1,1,10.0
+dispute,1,1,4.0
+chargeback,1,1,1.5"#;
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let mut processor = TransactionProcessor::new(2, 10).await;
+
+    processor.process(reader).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
+    let output_str = String::from_utf8(output).unwrap();
+    // 1.5 reversed, 2.5 still held, account locked by the chargeback
+    assert!(output_str.contains("1,6.0000,2.5000,8.5000,true"));
+}
+
+#[tokio::test]
+async fn test_refund() {
+    let csv_data = r#"type,client,tx,amount
+deposit,1,1,10.0
+withdrawal,1,2,4.0
+refund,1,1,3.0
+reversal,1,2,"#;
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let mut processor = TransactionProcessor::new(2, 10).await;
+
+    processor.process(reader).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
+    let output_str = String::from_utf8(output).unwrap();
+    // Refunds never lock the account
+    assert!(output_str.contains("1,7.0000,0.0000,7.0000,false"));
+}
+
+#[tokio::test]
+async fn test_fees_credited_to_house_account() {
+    let csv_data = r#"type,client,tx,amount
+deposit,1,1,100.0
+deposit,2,2,100.0
+withdrawal,1,3,50.0
+withdrawal,2,4,10.0"#;
+
+    let fees = FeeSchedule::new(99)
+        .with_withdrawal_fee(FeeRule::new(FeeKind::Percentage(Decimal::ONE)).with_min(Decimal::ONE));
+    let config = WalletConfig::default().with_fees(fees);
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let mut processor = TransactionProcessor::with_config(2, 10, config).await;
+
+    processor.process(reader).await.unwrap();
+
+    // The fraud report is asked behind the transactions, so they are all applied once
+    // it is written
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(Vec::new()),
+    };
+    processor.fraud_report(writer).await.unwrap();
+
+    let mut balances = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut balances),
+    };
+    processor.balances(writer).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
+    // Fees are credited as they are charged, so the balances already carry them
+    let balances_str = String::from_utf8(balances).unwrap();
+    let output_str = String::from_utf8(output).unwrap();
+    for rows in [balances_str, output_str] {
+        assert!(rows.contains("1,49.0000,0.0000,49.0000,false"));
+        assert!(rows.contains("2,89.0000,0.0000,89.0000,false"));
+        // Fees from both actors end up in a single house account row
+        assert!(rows.contains("99,2.0000,0.0000,2.0000,false"));
+        assert_eq!(rows.matches("\n99,").count(), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_overdraft_limits() {
+    let limits_data = r#"client,limit
+1,20.0"#;
+    let csv_data = r#"type,client,tx,amount
+deposit,1,1,10.0
+withdrawal,1,2,25.0
+deposit,2,3,10.0
+overdraft,2,4,5.0
+withdrawal,2,5,15.0
+withdrawal,2,6,1.0"#;
+
+    let limits = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(limits_data.as_bytes()),
+    };
+    let config = WalletConfig::default()
+        .with_admin_overdrafts(Decimal::ONE_HUNDRED)
+        .load_overdraft_limits(limits)
+        .await
+        .unwrap();
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let mut processor = TransactionProcessor::with_config(2, 10, config).await;
+
+    processor.process(reader).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
+    let output_str = String::from_utf8(output).unwrap();
+    assert!(output_str.contains("1,-15.0000,0.0000,-15.0000,false"));
+    // Second withdrawal would exceed the limit set by the admin transaction
+    assert!(output_str.contains("2,-5.0000,0.0000,-5.0000,false"));
+}
+
+struct BlockedClient(ClientId);
+
+impl TransactionRule for BlockedClient {
+    fn check(&self, tx: &Transaction) -> ProcessorResult<()> {
+        if tx.client == self.0 {
+            return Err(ProcessorError::InvalidTransaction {
+                message: format!("client {} is blocked", tx.client),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_custom_rules_and_invalid_rows_are_skipped() {
+    let csv_data = r#"type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,10.0
+withdrawal,1,3,
+deposit,1,4,5.0"#;
+
+    let config = WalletConfig::default().with_rule(Arc::new(BlockedClient(2)));
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let mut processor = TransactionProcessor::with_config(2, 10, config).await;
+
+    processor.process(reader).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
+    let output_str = String::from_utf8(output).unwrap();
+    // The withdrawal without an amount is rejected, the rest of the file still applies
+    assert!(output_str.contains("1,15.0000,0.0000,15.0000,false"));
+    assert!(!output_str.contains("\n2,"));
+}
+
+#[tokio::test]
+async fn test_fraud_report() {
+    let csv_data = r#"type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,2,10.0
+dispute,1,1,
+chargeback,1,1,
+dispute,1,2,
+chargeback,1,2,
+deposit,2,3,100.0
+withdrawal,2,4,95.0"#;
+
+    let config = WalletConfig::default().with_fraud(FraudConfig::recommended());
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let mut processor = TransactionProcessor::with_config(2, 10, config).await;
+
+    processor.process(reader).await.unwrap();
+
+    let mut report = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut report),
+    };
+    processor.fraud_report(writer).await.unwrap();
+
+    let report_str = String::from_utf8(report).unwrap();
+    assert!(report_str.contains("1,high_dispute_ratio"));
+    assert!(report_str.contains("1,repeated_chargebacks"));
+    assert!(report_str.contains("2,large_withdrawal_after_deposit"));
+}
+
+#[tokio::test]
+async fn test_precision_and_rounding() {
+    let csv_data = r#"type,client,tx,amount
+deposit,1,1,1.00005
+deposit,1,2,1.00015
+deposit,2,3,2.123456"#;
+
+    let config = WalletConfig::default().with_precision(Precision {
+        scale: 2,
+        rounding: Rounding::HalfUp,
+        strict: false,
+    });
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let mut processor = TransactionProcessor::with_config(2, 10, config).await;
+
+    processor.process(reader).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
+    let output_str = String::from_utf8(output).unwrap();
+    // Amounts are rounded at ingestion, so the sub-cent parts never add up
+    assert!(output_str.contains("1,2.00,0.00,2.00,false"));
+    assert!(output_str.contains("2,2.12,0.00,2.12,false"));
+}
+
+#[tokio::test]
+async fn test_wide_identifiers() {
+    let csv_data = r#"type,client,tx,amount
+deposit,70000,5000000000,10.0
+deposit,18446744073709551615,1,2.0
+dispute,70000,5000000000,"#;
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let mut processor = TransactionProcessor::new(3, 10).await;
+
+    processor.process(reader).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
+    let output_str = String::from_utf8(output).unwrap();
+    assert!(output_str.contains("70000,0.0000,10.0000,10.0000,false"));
+    assert!(output_str.contains("18446744073709551615,2.0000,0.0000,2.0000,false"));
+}
+
+#[tokio::test]
+async fn test_timestamps_drive_daily_limits() {
+    // 86400 seconds apart: the third withdrawal falls on the next day
+    let csv_data = r#"type,client,tx,amount,timestamp
+deposit,1,1,100.0,1000
+withdrawal,1,2,40.0,2000
+withdrawal,1,3,40.0,3000
+withdrawal,1,4,40.0,90000"#;
+
+    let velocity = VelocityConfig {
+        default: VelocityLimits {
+            max_daily_withdrawal: Some(Decimal::from(50)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let config = WalletConfig::default().with_velocity(velocity).with_error_report(true);
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let mut processor = TransactionProcessor::with_config(2, 10, config).await;
+
+    processor.process(reader).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
+    let output_str = String::from_utf8(output).unwrap();
+    assert!(output_str.contains("1,20.0000,0.0000,20.0000,false"));
+
+    let mut errors = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut errors),
+    };
+    processor.error_report(writer).await.unwrap();
+
+    let errors_str = String::from_utf8(errors).unwrap();
+    assert!(errors_str.contains(",withdrawal,1,3,40.0,3000\n"));
+}
+
+#[tokio::test]
+async fn test_reorder_buffer_applies_late_transactions() {
+    let csv_data = r#"type,client,tx,amount
+dispute,1,1,
+chargeback,1,1,
+deposit,1,1,10.0
+deposit,1,2,5.0
+dispute,2,7,
+dispute,2,8,
+dispute,2,9,"#;
+
+    let config = WalletConfig::default().with_reorder_buffer(2);
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let mut processor = TransactionProcessor::with_config(2, 10, config).await;
+
+    processor.process(reader).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
+    let output_str = String::from_utf8(output).unwrap();
+    // The dispute and chargeback were applied once the deposit arrived, so the
+    // account was already locked for the second deposit
+    assert!(output_str.contains("1,0.0000,0.0000,0.0000,true"));
+
+    let mut expired = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut expired),
+    };
+    processor.expired_report(writer).await.unwrap();
+
+    let expired_str = String::from_utf8(expired).unwrap();
+    assert!(expired_str.starts_with("type,client,tx,amount,timestamp,reason\n"));
+    assert!(expired_str.contains("dispute,2,7,,,buffer_full"));
+    assert!(expired_str.contains("dispute,2,8,,,unmatched"));
+    assert!(expired_str.contains("dispute,2,9,,,unmatched"));
+}
+
+#[tokio::test]
+async fn test_merged_inputs_are_interleaved_by_timestamp() {
+    let first = r#"type,client,tx,amount,timestamp
+deposit,1,1,10.0,100
+withdrawal,1,3,15.0,300"#;
+    let second = r#"type,client,tx,amount,timestamp
+deposit,1,2,10.0,200
+dispute,1,1,,400"#;
+
+    let readers = vec![
+        CsvStreamReader {
+            reader: AsyncReaderBuilder::new().create_deserializer(first.as_bytes()),
+        },
+        CsvStreamReader {
+            reader: AsyncReaderBuilder::new().create_deserializer(second.as_bytes()),
+        },
+    ];
+    let mut processor = TransactionProcessor::new(2, 10).await;
+
+    processor.process_merged(readers).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
+    let output_str = String::from_utf8(output).unwrap();
+    // The withdrawal only succeeds because the second deposit was applied before it
+    assert!(output_str.contains("1,-5.0000,10.0000,5.0000,false"));
+}
+
+#[tokio::test]
+async fn test_partner_dialects() {
+    let renamed = r#"kind;client_id;tx_id;amount
+deposit;1;1;10.0
+withdrawal;1;2;4.0"#;
+    let headerless = r#"2|deposit|3|5.0
+1|deposit|4|1.0"#;
+
+    let mut processor = TransactionProcessor::new(2, 10).await;
+
+    let dialect = CsvDialect::default()
+        .with_delimiter(b';')
+        .with_column_name("kind", "type")
+        .with_column_name("client_id", "client")
+        .with_column_name("tx_id", "tx");
+    let reader = dialect.reader(renamed.as_bytes()).await.unwrap();
+    processor.process(reader).await.unwrap();
+
+    let dialect = CsvDialect::default()
+        .with_delimiter(b'|')
+        .without_headers(["client", "type", "tx", "amount"].map(String::from).to_vec());
+    let reader = dialect.reader(headerless.as_bytes()).await.unwrap();
+    processor.process(reader).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
+    let output_str = String::from_utf8(output).unwrap();
+    assert!(output_str.contains("1,7.0000,0.0000,7.0000,false"));
+    assert!(output_str.contains("2,5.0000,0.0000,5.0000,false"));
+}
+
+#[tokio::test]
+async fn test_error_report() {
+    let csv_data = r#"type,client,tx,amount
+deposit,1,3,-1.0
+deposit,1,1,1.0
+withdrawal,1,2,5.0
+dispute,1,9,"#;
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let config = WalletConfig::default().with_error_report(true);
+    let mut processor = TransactionProcessor::with_config(2, 10, config).await;
+
+    processor.process(reader).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
+    let mut errors = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut errors),
+    };
+    processor.error_report(writer).await.unwrap();
+
+    let errors_str = String::from_utf8(errors).unwrap();
+    let rows: Vec<&str> = errors_str.lines().collect();
+    assert_eq!(rows[0], "actor,error,type,client,tx,amount,timestamp");
+    assert_eq!(rows.len(), 4);
+    assert!(rows[1].starts_with("processor,"));
+    assert!(rows[1].ends_with(",deposit,1,3,-1.0,"));
+    assert!(rows[2].starts_with("wallet-1,\"Insufficient funds"));
+    assert!(rows[2].ends_with(",withdrawal,1,2,5.0,"));
+    assert_eq!(rows[3], "wallet-1,Transaction not found: 9,dispute,1,9,,");
+}
+
+#[tokio::test]
+async fn test_balances_do_not_consume_wallets() {
+    let csv_data = r#"type,client,tx,amount
+deposit,1,1,3.0
+deposit,2,2,2.0"#;
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let mut processor = TransactionProcessor::new(2, 10).await;
+
+    processor.process(reader).await.unwrap();
+
+    // The fraud report is asked behind the transactions, so they are all applied once
+    // it is written
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(Vec::new()),
+    };
+    processor.fraud_report(writer).await.unwrap();
+
+    let mut balances = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut balances),
+    };
+    processor.balances(writer).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
+    let balances_str = String::from_utf8(balances).unwrap();
+    let output_str = String::from_utf8(output).unwrap();
+    for rows in [balances_str, output_str] {
+        assert!(rows.contains("1,3.0000,0.0000,3.0000,false"));
+        assert!(rows.contains("2,2.0000,0.0000,2.0000,false"));
+    }
+}
+
+#[tokio::test]
+async fn test_batched_delivery() {
+    let csv_data = r#"type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+withdrawal,1,3,4.0
+dispute,1,1,
+deposit,3,4,1.0
+resolve,1,1,"#;
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let config = WalletConfig::default().with_batching(4, std::time::Duration::from_millis(10));
+    let mut processor = TransactionProcessor::with_config(2, 10, config).await;
+
+    processor.process(reader).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    processor.output(writer).await.unwrap();
+
+    let output_str = String::from_utf8(output).unwrap();
+    assert!(output_str.contains("1,6.0000,0.0000,6.0000,false"));
+    assert!(output_str.contains("2,5.0000,0.0000,5.0000,false"));
+    assert!(output_str.contains("3,1.0000,0.0000,1.0000,false"));
+}
+
+#[tokio::test]
+async fn test_wallet_actors_are_registered() {
+    let csv_data = r#"type,client,tx,amount
+deposit,1,1,3.0
+deposit,2,2,2.0"#;
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let mut processor = TransactionProcessor::new(2, 10).await;
+    processor.process(reader).await.unwrap();
+
+    let registry = processor.registry().clone();
+    assert_eq!(registry.names(), vec!["wallet-0", "wallet-1"]);
+    assert!(registry.lookup::<Transaction>("wallet-0").is_none());
+
+    // Client 1 is on the second WalletActor
+    let wallet_actor = registry.lookup::<WalletActorMessages>("wallet-1").unwrap();
+    let balances = wallet_actor
+        .ask_with(WalletActorMessages::Balances, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(balances.len(), 1);
+    assert_eq!(balances[0].client, 1);
+    assert_eq!(balances[0].wallet.available, Decimal::from(3));
+
+    processor.shutdown().await.unwrap();
+    assert!(registry.names().is_empty());
+}
+
+#[tokio::test]
+async fn test_output_fails_when_a_wallet_actor_does_not_reply() {
+    let csv_data = r#"type,client,tx,amount
+deposit,1,1,3.0
+deposit,2,2,2.0"#;
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let mut processor = TransactionProcessor::new(2, 10).await;
+    processor.process(reader).await.unwrap();
+
+    let wallet_actor = processor.registry().lookup::<WalletActorMessages>("wallet-1").unwrap();
+    wallet_actor.kill().await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    let result = processor.output(writer).await;
+
+    assert!(matches!(result, Err(ProcessorError::MissingReplies { actors }) if actors.starts_with("wallet-1 (")));
+    assert!(output.is_empty());
+}
+
+// Holds up the WalletActor applying the transaction
+struct SlowWallet;
+
+impl TransactionRule for SlowWallet {
+    fn check_wallet(&self, _tx: &Transaction, _wallet: &Wallet) -> ProcessorResult<()> {
+        std::thread::sleep(Duration::from_millis(500));
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_output_fails_when_replies_time_out() {
+    let csv_data = r#"type,client,tx,amount
+deposit,1,1,3.0"#;
+
+    let reader = CsvStreamReader {
+        reader: AsyncReaderBuilder::new().create_deserializer(csv_data.as_bytes()),
+    };
+    let config = WalletConfig::default()
+        .with_rule(Arc::new(SlowWallet))
+        .with_reply_timeout(Duration::from_millis(50));
+    let mut processor = TransactionProcessor::with_config(2, 10, config).await;
+    processor.process(reader).await.unwrap();
+
+    let mut output = Vec::new();
+    let writer = CsvStreamWriter {
+        writer: AsyncWriterBuilder::new().create_serializer(&mut output),
+    };
+    let result = processor.output(writer).await;
+
+    assert!(
+        matches!(result, Err(ProcessorError::MissingReplies { actors }) if actors.starts_with("wallet-1 (Actor did not reply"))
+    );
+    assert!(output.is_empty());
+}